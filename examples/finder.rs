@@ -7,6 +7,7 @@ use std::rc::Rc;
 use termfest::{Cell, DisplayWidth, Event, ScreenLock, Termfest};
 use termfest::key::*;
 use termfest::attr::*;
+use termfest::text::{Line, Span};
 
 #[derive(Default)]
 struct Finder {
@@ -118,22 +119,16 @@ impl Finder {
                     &m[i + self.needle.len()..],
                 ),
             };
-            screen.print(2, i + 1, before, attr);
-            screen.print(
-                2 + before.display_width(),
-                i + 1,
-                mat,
-                Attribute {
-                    fg: Color::Red,
-                    ..attr
-                },
-            );
-            screen.print(
-                2 + before.display_width() + mat.display_width(),
-                i + 1,
-                after,
-                attr,
-            );
+            let highlight = Attribute {
+                fg: Color::Red,
+                ..attr
+            };
+            let line = Line::new(vec![
+                Span::styled(before, attr),
+                Span::styled(mat, highlight),
+                Span::styled(after, attr),
+            ]);
+            screen.print_line(2, i + 1, &line);
         }
     }
 
@@ -178,6 +173,9 @@ fn main() {
             Event::Key(ArrowDown) | Event::Key(CtrlN) => {
                 finder.down();
             }
+            Event::Key(CtrlL) => {
+                fest.redraw_all().unwrap();
+            }
             Event::Key(ENTER) => {
                 result = Some(finder.get());
                 break;
@@ -9,7 +9,7 @@ use std::default::Default;
 ///
 /// Attribute { fg: Color::Red, effect: Effect::BOLD, ..Attribute::default() };
 /// ```
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Attribute {
     pub fg: Color,
     pub bg: Color,
@@ -26,7 +26,7 @@ impl Default for Attribute {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Color {
     Default,
     Black,
@@ -0,0 +1,298 @@
+//! This module defines border styles and how lines of box-drawing characters are merged where
+//! they meet.
+
+/// `BorderStyle` is a style of lines drawn by `ScreenLock::hline`, `ScreenLock::vline` and
+/// `ScreenLock::draw_box`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BorderStyle {
+    /// `┌─┐`
+    Single,
+    /// `╔═╗`
+    Double,
+    /// `╭─╮`
+    Rounded,
+    /// `┏━┓`
+    Thick,
+    /// `+-+`
+    Ascii,
+}
+
+// the weights of the arms of a box-drawing character, in the order of up, down, left and right.
+pub(crate) type Arms = [u8; 4];
+
+const N: u8 = 0;
+const L: u8 = 1;
+const H: u8 = 2;
+const D: u8 = 3;
+const A: u8 = 4;
+
+pub(crate) const UP: usize = 0;
+pub(crate) const DOWN: usize = 1;
+pub(crate) const LEFT: usize = 2;
+pub(crate) const RIGHT: usize = 3;
+
+pub(crate) const HORIZONTAL: Arms = [N, N, 1, 1];
+pub(crate) const VERTICAL: Arms = [1, 1, N, N];
+
+// box-drawing characters except dashed and diagonal ones.
+static GLYPHS: &[(char, Arms)] = &[
+    ('─', [N, N, L, L]),
+    ('━', [N, N, H, H]),
+    ('│', [L, L, N, N]),
+    ('┃', [H, H, N, N]),
+    ('┌', [N, L, N, L]),
+    ('┍', [N, L, N, H]),
+    ('┎', [N, H, N, L]),
+    ('┏', [N, H, N, H]),
+    ('┐', [N, L, L, N]),
+    ('┑', [N, L, H, N]),
+    ('┒', [N, H, L, N]),
+    ('┓', [N, H, H, N]),
+    ('└', [L, N, N, L]),
+    ('┕', [L, N, N, H]),
+    ('┖', [H, N, N, L]),
+    ('┗', [H, N, N, H]),
+    ('┘', [L, N, L, N]),
+    ('┙', [L, N, H, N]),
+    ('┚', [H, N, L, N]),
+    ('┛', [H, N, H, N]),
+    ('├', [L, L, N, L]),
+    ('┝', [L, L, N, H]),
+    ('┞', [H, L, N, L]),
+    ('┟', [L, H, N, L]),
+    ('┠', [H, H, N, L]),
+    ('┡', [H, L, N, H]),
+    ('┢', [L, H, N, H]),
+    ('┣', [H, H, N, H]),
+    ('┤', [L, L, L, N]),
+    ('┥', [L, L, H, N]),
+    ('┦', [H, L, L, N]),
+    ('┧', [L, H, L, N]),
+    ('┨', [H, H, L, N]),
+    ('┩', [H, L, H, N]),
+    ('┪', [L, H, H, N]),
+    ('┫', [H, H, H, N]),
+    ('┬', [N, L, L, L]),
+    ('┭', [N, L, H, L]),
+    ('┮', [N, L, L, H]),
+    ('┯', [N, L, H, H]),
+    ('┰', [N, H, L, L]),
+    ('┱', [N, H, H, L]),
+    ('┲', [N, H, L, H]),
+    ('┳', [N, H, H, H]),
+    ('┴', [L, N, L, L]),
+    ('┵', [L, N, H, L]),
+    ('┶', [L, N, L, H]),
+    ('┷', [L, N, H, H]),
+    ('┸', [H, N, L, L]),
+    ('┹', [H, N, H, L]),
+    ('┺', [H, N, L, H]),
+    ('┻', [H, N, H, H]),
+    ('┼', [L, L, L, L]),
+    ('┽', [L, L, H, L]),
+    ('┾', [L, L, L, H]),
+    ('┿', [L, L, H, H]),
+    ('╀', [H, L, L, L]),
+    ('╁', [L, H, L, L]),
+    ('╂', [H, H, L, L]),
+    ('╃', [H, L, H, L]),
+    ('╄', [H, L, L, H]),
+    ('╅', [L, H, H, L]),
+    ('╆', [L, H, L, H]),
+    ('╇', [H, L, H, H]),
+    ('╈', [L, H, H, H]),
+    ('╉', [H, H, H, L]),
+    ('╊', [H, H, L, H]),
+    ('╋', [H, H, H, H]),
+    ('═', [N, N, D, D]),
+    ('║', [D, D, N, N]),
+    ('╒', [N, L, N, D]),
+    ('╓', [N, D, N, L]),
+    ('╔', [N, D, N, D]),
+    ('╕', [N, L, D, N]),
+    ('╖', [N, D, L, N]),
+    ('╗', [N, D, D, N]),
+    ('╘', [L, N, N, D]),
+    ('╙', [D, N, N, L]),
+    ('╚', [D, N, N, D]),
+    ('╛', [L, N, D, N]),
+    ('╜', [D, N, L, N]),
+    ('╝', [D, N, D, N]),
+    ('╞', [L, L, N, D]),
+    ('╟', [D, D, N, L]),
+    ('╠', [D, D, N, D]),
+    ('╡', [L, L, D, N]),
+    ('╢', [D, D, L, N]),
+    ('╣', [D, D, D, N]),
+    ('╤', [N, L, D, D]),
+    ('╥', [N, D, L, L]),
+    ('╦', [N, D, D, D]),
+    ('╧', [L, N, D, D]),
+    ('╨', [D, N, L, L]),
+    ('╩', [D, N, D, D]),
+    ('╪', [L, L, D, D]),
+    ('╫', [D, D, L, L]),
+    ('╬', [D, D, D, D]),
+    ('╴', [N, N, L, N]),
+    ('╵', [L, N, N, N]),
+    ('╶', [N, N, N, L]),
+    ('╷', [N, L, N, N]),
+    ('╸', [N, N, H, N]),
+    ('╹', [H, N, N, N]),
+    ('╺', [N, N, N, H]),
+    ('╻', [N, H, N, N]),
+    ('╼', [N, N, L, H]),
+    ('╽', [L, H, N, N]),
+    ('╾', [N, N, H, L]),
+    ('╿', [H, L, N, N]),
+];
+
+// rounded corners are used only by `BorderStyle::Rounded`, and decomposed like other corners.
+static ARCS: &[(char, Arms)] = &[
+    ('╭', [N, L, N, L]),
+    ('╮', [N, L, L, N]),
+    ('╯', [L, N, L, N]),
+    ('╰', [L, N, N, L]),
+];
+
+impl BorderStyle {
+    fn weight(&self) -> u8 {
+        match *self {
+            BorderStyle::Single | BorderStyle::Rounded => L,
+            BorderStyle::Thick => H,
+            BorderStyle::Double => D,
+            BorderStyle::Ascii => A,
+        }
+    }
+
+    /// returns the character of `arms` (whose non-zero weights are replaced) in this style.
+    fn glyph(&self, arms: Arms) -> char {
+        let weight = self.weight();
+        let arms = with_weight(arms, weight);
+        if *self == BorderStyle::Rounded {
+            if let Some(&(ch, _)) = ARCS.iter().find(|&&(_, a)| a == arms) {
+                return ch;
+            }
+        }
+        lookup(arms, weight)
+    }
+}
+
+fn with_weight(arms: Arms, weight: u8) -> Arms {
+    let mut result = [N; 4];
+    for (r, &a) in result.iter_mut().zip(arms.iter()) {
+        if a != N {
+            *r = weight;
+        }
+    }
+    result
+}
+
+fn arms_of(ch: char) -> Arms {
+    match ch {
+        '-' => [N, N, A, A],
+        '|' => [A, A, N, N],
+        '+' => [A, A, A, A],
+        _ => GLYPHS
+            .iter()
+            .chain(ARCS.iter())
+            .find(|&&(c, _)| c == ch)
+            .map(|&(_, arms)| arms)
+            .unwrap_or([N; 4]),
+    }
+}
+
+// returns the character of `arms`, or the closest one with `weight` if there is no such character.
+fn lookup(arms: Arms, weight: u8) -> char {
+    if arms.contains(&A) {
+        return if arms[LEFT] == N && arms[RIGHT] == N {
+            '|'
+        } else if arms[UP] == N && arms[DOWN] == N {
+            '-'
+        } else {
+            '+'
+        };
+    }
+    if let Some(&(ch, _)) = GLYPHS.iter().find(|&&(_, a)| a == arms) {
+        return ch;
+    }
+    // some combinations of weights, like double half lines, have no character.
+    let mut uniform = with_weight(arms, weight);
+    if uniform.iter().filter(|&&a| a != N).count() == 1 {
+        let axis = if uniform[UP] != N || uniform[DOWN] != N {
+            VERTICAL
+        } else {
+            HORIZONTAL
+        };
+        uniform = with_weight(axis, weight);
+    }
+    GLYPHS
+        .iter()
+        .find(|&&(_, a)| a == uniform)
+        .map(|&(ch, _)| ch)
+        .unwrap_or(' ')
+}
+
+/// returns the box-drawing character `ch` redrawn in `style`, like '┼' for '╬' in
+/// `BorderStyle::Single`. It returns `None` if `ch` is not a box-drawing character.
+pub(crate) fn restyle(ch: char, style: BorderStyle) -> Option<char> {
+    if ch.is_ascii() {
+        return None;
+    }
+    let arms = arms_of(ch);
+    if arms == [N; 4] {
+        None
+    } else {
+        Some(style.glyph(arms))
+    }
+}
+
+/// returns true if `ch` is a part of a line, like '─', '┼' or '+'.
+pub(crate) fn is_line(ch: char) -> bool {
+    arms_of(ch) != [N; 4]
+}
+
+/// returns the character to draw `arms` of `style` over `existing`, joining the lines of
+/// `existing` into a junction like '┼' or '├'. The arms of the new line take precedence.
+pub(crate) fn merge(existing: char, arms: Arms, style: BorderStyle) -> char {
+    let old = arms_of(existing);
+    let new = with_weight(arms, style.weight());
+    let mut merged = new;
+    for (m, &o) in merged.iter_mut().zip(old.iter()) {
+        if *m == N && o != N {
+            // ascii lines can't be mixed with box-drawing characters.
+            *m = if (o == A) == (style.weight() == A) {
+                o
+            } else {
+                style.weight()
+            };
+        }
+    }
+    if merged == new {
+        style.glyph(arms)
+    } else {
+        lookup(merged, style.weight())
+    }
+}
+
+#[test]
+fn test_merge() {
+    use self::BorderStyle::*;
+
+    assert_eq!(merge(' ', HORIZONTAL, Single), '─');
+    assert_eq!(merge(' ', [N, 1, N, 1], Rounded), '╭');
+    assert_eq!(merge('─', VERTICAL, Single), '┼');
+    assert_eq!(merge('│', [N, N, N, 1], Single), '├');
+    assert_eq!(merge('┐', [N, 1, 1, 1], Single), '┬');
+    assert_eq!(merge('╭', [N, 1, 1, N], Rounded), '┬');
+    assert_eq!(merge('║', [N, N, 1, N], Single), '╢');
+    assert_eq!(merge('━', VERTICAL, Single), '┿');
+    assert_eq!(merge('═', [N, 1, N, N], Thick), '┳');
+    assert_eq!(merge('|', HORIZONTAL, Ascii), '+');
+    assert_eq!(merge('─', VERTICAL, Ascii), '+');
+    assert_eq!(merge('x', VERTICAL, Double), '║');
+
+    assert_eq!(restyle('╬', Single), Some('┼'));
+    assert_eq!(restyle('╮', Ascii), Some('+'));
+    assert_eq!(restyle('-', Single), None);
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use num::FromPrimitive;
 
 use terminal::Terminal;
@@ -17,29 +19,215 @@ pub enum Event {
     },
 }
 
+/// `Reply` is a response of the terminal to a query sent by termfest.
+/// Replies are consumed by termfest itself and never delivered as `Event`s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reply {
+    /// a color reply. The color is `None` if its format is not supported.
+    Color(ColorSlot, Option<(u8, u8, u8)>),
+    /// DECRPM, a report of the state of a DEC private mode.
+    /// `status` is 0 if the mode is not recognized, 1 or 2 if it is set or reset, and 3 or 4 if
+    /// it is permanently set or reset.
+    Mode { mode: u16, status: u8 },
+}
+
+/// `ColorSlot` is a color of the terminal that can be queried with OSC 10, 11 or 4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSlot {
+    Foreground,
+    Background,
+    Palette(u8),
+}
+
+/// `Input` is a unit of bytes read from the terminal.
+#[derive(Debug, Clone)]
+pub enum Input {
+    Event(Event),
+    Reply(Reply),
+}
+
+/// bytes which may be a prefix of a reply wait for the rest this long, and are parsed as keys
+/// after that, so that keys like Alt-] are not held back.
+pub const REPLY_TIMEOUT: Duration = Duration::from_millis(50);
+
 /// Parse event from buffer.
 /// `None` means 'buffered bytes are not enough'.
-pub fn parse(buf: &[u8], term: &Terminal) -> Option<(usize, Event)> {
+/// If `timed_out` is true, the bytes have waited longer than `REPLY_TIMEOUT`, and a prefix of a
+/// reply is parsed as keys.
+pub fn parse(buf: &[u8], term: &Terminal, timed_out: bool) -> Option<(usize, Input)> {
     if buf.is_empty() {
         return None;
     }
     if buf[0] == b'\x1b' {
+        match parse_reply(buf, term.color_query_pending()) {
+            Partial::Done(n, reply) => return Some((n, Input::Reply(reply))),
+            Partial::Incomplete if !timed_out => return None,
+            Partial::Incomplete | Partial::Invalid => {}
+        }
         // escape sequence
-        if let Some(result) = parse_escape_sequence(buf, term) {
-            return Some(result);
+        if let Some((n, ev)) = parse_escape_sequence(buf, term) {
+            return Some((n, Input::Event(ev)));
         }
     }
 
     if let Some(key) = key_from_byte(buf[0]) {
         // for single-byte keys like ctrl-A
-        return Some((1, Event::Key(key)));
+        return Some((1, Input::Event(Event::Key(key))));
     }
 
     let (len_utf8, ch) = match decode_char(buf) {
         None => return None,
         Some(r) => r,
     };
-    Some((len_utf8, Event::Char(ch)))
+    Some((len_utf8, Input::Event(Event::Char(ch))))
+}
+
+#[derive(Debug, PartialEq)]
+enum Partial<T> {
+    Done(usize, T),
+    /// `buf` is a prefix of a `T`, and more bytes are needed.
+    Incomplete,
+    Invalid,
+}
+
+// replies longer than this are not what termfest asked for.
+const MAX_REPLY_LEN: usize = 64;
+
+/// Parse replies at the start of `buf`. OSC color replies are expected only if `color_queried`
+/// is true, because `ESC ]` is also sent by Alt-].
+fn parse_reply(buf: &[u8], color_queried: bool) -> Partial<Reply> {
+    if buf.starts_with(b"\x1b[?") {
+        parse_mode_reply(buf)
+    } else if color_queried {
+        parse_color_reply(buf)
+    } else {
+        Partial::Invalid
+    }
+}
+
+/// Parse DECRPM replies like `ESC [ ? 2026 ; 2 $ y`.
+fn parse_mode_reply(buf: &[u8]) -> Partial<Reply> {
+    let body_len = buf[3..]
+        .iter()
+        .take_while(|&&b| b.is_ascii_digit() || b == b';')
+        .count();
+    let rest = &buf[3 + body_len..];
+    if rest.is_empty() || rest == b"$" {
+        return if buf.len() < MAX_REPLY_LEN {
+            Partial::Incomplete
+        } else {
+            Partial::Invalid
+        };
+    }
+    if !rest.starts_with(b"$y") {
+        return Partial::Invalid;
+    }
+    // `body` consists of ascii characters only.
+    let body = ::std::str::from_utf8(&buf[3..3 + body_len]).unwrap();
+    let mut fields = body.split(';');
+    match (
+        fields.next().and_then(|f| f.parse().ok()),
+        fields.next().and_then(|f| f.parse().ok()),
+        fields.next(),
+    ) {
+        (Some(mode), Some(status), None) => {
+            Partial::Done(3 + body_len + 2, Reply::Mode { mode, status })
+        }
+        _ => Partial::Invalid,
+    }
+}
+
+/// Parse OSC color replies like `ESC ] 11 ; rgb:ffff/ffff/ffff BEL`.
+/// The terminator can be either of BEL or ST (`ESC \`).
+fn parse_color_reply(buf: &[u8]) -> Partial<Reply> {
+    if !buf.starts_with(b"\x1b]") {
+        return Partial::Invalid;
+    }
+    // every reply starts with the number of the OSC, like `11;`.
+    let digits = buf[2..].iter().take_while(|b| b.is_ascii_digit()).count();
+    match buf.get(2 + digits) {
+        None => return Partial::Incomplete,
+        Some(&b';') if digits > 0 => {}
+        Some(_) => return Partial::Invalid,
+    }
+    let body_len = buf[2..]
+        .iter()
+        .take_while(|&&b| b.is_ascii_graphic())
+        .count();
+    let body = &buf[2..2 + body_len];
+    let rest = &buf[2 + body_len..];
+    let terminator_len = if rest.is_empty() || rest == b"\x1b" {
+        return if buf.len() < MAX_REPLY_LEN {
+            Partial::Incomplete
+        } else {
+            Partial::Invalid
+        };
+    } else if rest[0] == b'\x07' {
+        1
+    } else if rest.starts_with(b"\x1b\\") {
+        2
+    } else {
+        return Partial::Invalid;
+    };
+    // `body` consists of ascii characters only.
+    let body = ::std::str::from_utf8(body).unwrap();
+    let fields: Vec<_> = body.split(';').collect();
+    let (slot, spec) = match (fields.len(), fields[0]) {
+        (2, "10") => (ColorSlot::Foreground, fields[1]),
+        (2, "11") => (ColorSlot::Background, fields[1]),
+        (3, "4") => match fields[1].parse() {
+            Ok(index) => (ColorSlot::Palette(index), fields[2]),
+            Err(_) => return Partial::Invalid,
+        },
+        _ => return Partial::Invalid,
+    };
+    // a reply in an unknown format is still consumed, so that it does not leak as keys.
+    let reply = Reply::Color(slot, parse_color_spec(spec));
+    Partial::Done(2 + body_len + terminator_len, reply)
+}
+
+/// Parse X11 color specs like `rgb:ffff/8080/0000`, `rgba:ffff/8080/0000/ffff` (whose alpha
+/// is ignored) and `#ff8000`.
+fn parse_color_spec(spec: &str) -> Option<(u8, u8, u8)> {
+    if let Some(hex) = spec.strip_prefix('#') {
+        // `#` is followed by components of the same number of hex digits, which are the most
+        // significant bits.
+        let len = hex.len() / 3;
+        if len == 0 || hex.len() != len * 3 || len > 4 || !hex.is_ascii() {
+            return None;
+        }
+        let components = (0..3).map(|i| &hex[i * len..(i + 1) * len]).collect();
+        return parse_components(components, |value, len| (value << 8) >> (4 * len));
+    }
+    let (body, n) = if let Some(rgb) = spec.strip_prefix("rgb:") {
+        (rgb, 3)
+    } else if let Some(rgba) = spec.strip_prefix("rgba:") {
+        (rgba, 4)
+    } else {
+        return None;
+    };
+    let components: Vec<_> = body.split('/').collect();
+    if components.len() != n {
+        return None;
+    }
+    // each component has 1 to 4 hex digits, and is scaled to 8 bits.
+    parse_components(components, |value, len| value * 255 / ((1 << (4 * len)) - 1))
+}
+
+/// Parse the first three of `components` as hex numbers, converting them to 8 bits with
+/// `to_8bit(value, digits)`.
+fn parse_components<F>(components: Vec<&str>, to_8bit: F) -> Option<(u8, u8, u8)>
+where
+    F: Fn(u32, usize) -> u32,
+{
+    let mut rgb = components.into_iter().take(3).map(|hex| {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(to_8bit(value, hex.len()) as u8)
+    });
+    Some((rgb.next()??, rgb.next()??, rgb.next()??))
 }
 
 // Copy from core/str/mod.rs in Rust.
@@ -132,3 +320,80 @@ fn test_decode_char() {
         assert_eq!(decode_char(bytes), expected);
     }
 }
+
+#[test]
+fn test_parse_reply() {
+    let tests: [(&[u8], Partial<Reply>); 12] = [
+        (
+            b"\x1b]11;rgb:ffff/8080/0000\x07a",
+            Partial::Done(
+                24,
+                Reply::Color(ColorSlot::Background, Some((255, 128, 0))),
+            ),
+        ),
+        (
+            b"\x1b]4;12;rgb:f/0/a\x1b\\",
+            Partial::Done(
+                18,
+                Reply::Color(ColorSlot::Palette(12), Some((255, 0, 170))),
+            ),
+        ),
+        (b"\x1b]10;rgb:ff", Partial::Incomplete),
+        (b"\x1b]10;rgb:ff/ff/ff\x1b", Partial::Incomplete),
+        (
+            b"\x1b]11;rgba:0000/8080/ffff/ffff\x07",
+            Partial::Done(
+                30,
+                Reply::Color(ColorSlot::Background, Some((0, 128, 255))),
+            ),
+        ),
+        (
+            b"\x1b]10;#f08000\x07",
+            Partial::Done(
+                13,
+                Reply::Color(ColorSlot::Foreground, Some((240, 128, 0))),
+            ),
+        ),
+        // replies in unknown formats are consumed without a color.
+        (
+            b"\x1b]10;?\x07",
+            Partial::Done(7, Reply::Color(ColorSlot::Foreground, None)),
+        ),
+        (b"\x1b]12;rgb:ff/ff/ff\x07", Partial::Invalid),
+        (b"\x1b[A", Partial::Invalid),
+        (
+            b"\x1b[?2026;2$y",
+            Partial::Done(
+                11,
+                Reply::Mode {
+                    mode: 2026,
+                    status: 2,
+                },
+            ),
+        ),
+        (b"\x1b[?2026;", Partial::Incomplete),
+        (b"\x1b[?2026;2y", Partial::Invalid),
+    ];
+    for &(bytes, ref expected) in tests.iter() {
+        assert_eq!(&parse_reply(bytes, true), expected);
+    }
+}
+
+#[test]
+fn test_parse_alt_bracket() {
+    let term = ::terminal::dumb_xterm();
+    let is_esc = |input: Option<(usize, Input)>| {
+        matches!(input, Some((1, Input::Event(Event::Key(Key::ESC)))))
+    };
+    // `ESC ]` is not a reply unless a color is queried.
+    assert_eq!(parse_reply(b"\x1b]11;rgb:ffff/ffff/ffff\x07", false), Partial::Invalid);
+    assert!(is_esc(parse(b"\x1b]", &term, false)));
+
+    term.query_color(Vec::new(), ColorSlot::Background).unwrap();
+    assert_eq!(parse_reply(b"\x1b]", true), Partial::Incomplete);
+    assert_eq!(parse_reply(b"\x1b]a", true), Partial::Invalid);
+    assert_eq!(parse_reply(b"\x1b];", true), Partial::Invalid);
+    assert!(is_esc(parse(b"\x1b]abc", &term, false)));
+    assert!(parse(b"\x1b]1", &term, false).is_none());
+    assert!(is_esc(parse(b"\x1b]1", &term, true)));
+}
@@ -0,0 +1,233 @@
+//! This module defines types to describe areas of the screen, and a way to split them by
+//! constraints.
+
+use std::cmp;
+
+/// `Constraint` decides the length of a part of a split `Rect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Constraint {
+    /// exactly the given number of cells.
+    Length(usize),
+    /// the given percentage of the whole length.
+    Percentage(u16),
+    /// `a / b` of the whole length.
+    Ratio(u32, u32),
+    /// at least the given number of cells, growing into the remaining space like `Fill(1)`.
+    Min(usize),
+    /// at most the given number of cells, growing into the remaining space like `Fill(1)`.
+    Max(usize),
+    /// a share of the remaining space, proportional to the weight.
+    Fill(u16),
+}
+
+/// `Direction` is the direction in which a `Rect` is split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// parts are placed from left to right.
+    Horizontal,
+    /// parts are placed from top to bottom.
+    Vertical,
+}
+
+/// `Rect` is a rectangular area of the screen, whose top-left corner is (x, y).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// returns the x coordinate just past the right edge.
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    /// returns the y coordinate just past the bottom edge.
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        self.x <= x && x < self.right() && self.y <= y && y < self.bottom()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// splits `self` into parts, one for each constraint.
+    ///
+    /// `Length`, `Percentage`, `Ratio` and `Min` are satisfied first, and proportional lengths
+    /// are rounded down, handing the lost cells to the parts with the largest fractions (the
+    /// earlier part wins a tie). If they don't fit, earlier parts take precedence and later
+    /// ones are shrunk. The rest of the space is shared by `Min`, `Max` and `Fill` parts in
+    /// proportion to their weights, with the remainder going to earlier parts.
+    /// Space which no part can take is left after the last part.
+    ///
+    /// ```
+    /// use termfest::layout::{Constraint, Direction, Rect};
+    ///
+    /// let screen = Rect::new(0, 0, 80, 24);
+    /// let rows = screen.split(
+    ///     Direction::Vertical,
+    ///     &[Constraint::Length(1), Constraint::Fill(1), Constraint::Length(1)],
+    /// );
+    /// assert_eq!(rows[1], Rect::new(0, 1, 80, 22));
+    /// ```
+    pub fn split(&self, direction: Direction, constraints: &[Constraint]) -> Vec<Rect> {
+        let total = match direction {
+            Direction::Horizontal => self.width,
+            Direction::Vertical => self.height,
+        };
+        let mut offset = 0;
+        solve(total, constraints)
+            .into_iter()
+            .map(|length| {
+                let rect = match direction {
+                    Direction::Horizontal => {
+                        Rect::new(self.x + offset, self.y, length, self.height)
+                    }
+                    Direction::Vertical => Rect::new(self.x, self.y + offset, self.width, length),
+                };
+                offset += length;
+                rect
+            })
+            .collect()
+    }
+
+    /// returns the area covered by both `self` and `other`.
+    /// If they don't overlap, the result is empty.
+    pub fn intersection(&self, other: Rect) -> Rect {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        let right = cmp::max(x, cmp::min(self.right(), other.right()));
+        let bottom = cmp::max(y, cmp::min(self.bottom(), other.bottom()));
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+// returns the lengths of the parts of `total` cells split by `constraints`.
+fn solve(total: usize, constraints: &[Constraint]) -> Vec<usize> {
+    let mut lengths = Vec::with_capacity(constraints.len());
+    // the fractional parts of proportional lengths, as (index, numerator, denominator).
+    let mut fractions = Vec::new();
+    for (i, constraint) in constraints.iter().enumerate() {
+        let (numerator, denominator) = match *constraint {
+            Constraint::Length(n) | Constraint::Min(n) => {
+                lengths.push(n);
+                continue;
+            }
+            Constraint::Max(_) | Constraint::Fill(_) => {
+                lengths.push(0);
+                continue;
+            }
+            Constraint::Percentage(p) => (total as u64 * u64::from(p), 100),
+            Constraint::Ratio(a, b) => (total as u64 * u64::from(a), u64::from(b)),
+        };
+        if denominator == 0 {
+            lengths.push(0);
+            continue;
+        }
+        lengths.push((numerator / denominator) as usize);
+        fractions.push((i, numerator % denominator, denominator));
+    }
+    let lost = fractions
+        .iter()
+        .map(|&(_, n, d)| n as f64 / d as f64)
+        .sum::<f64>()
+        .round() as usize;
+    fractions.sort_by(|a, b| (b.1 * a.2).cmp(&(a.1 * b.2)).then(a.0.cmp(&b.0)));
+    for &(i, _, _) in fractions.iter().take(lost) {
+        lengths[i] += 1;
+    }
+
+    let mut remaining = total;
+    for length in &mut lengths {
+        *length = cmp::min(*length, remaining);
+        remaining -= *length;
+    }
+
+    // (index, weight, maximum length) of parts growing into the remaining space.
+    let mut growing: Vec<(usize, usize, usize)> = constraints
+        .iter()
+        .enumerate()
+        .filter_map(|(i, constraint)| match *constraint {
+            Constraint::Min(_) => Some((i, 1, usize::MAX)),
+            Constraint::Max(n) => Some((i, 1, n)),
+            Constraint::Fill(w) if w > 0 => Some((i, usize::from(w), usize::MAX)),
+            _ => None,
+        })
+        .collect();
+    while remaining > 0 && !growing.is_empty() {
+        let weights: usize = growing.iter().map(|&(_, w, _)| w).sum();
+        let share = |w: usize| (remaining as u128 * w as u128 / weights as u128) as usize;
+        // parts reaching their maximum are settled first, and the rest is shared again.
+        let capped = growing
+            .iter()
+            .position(|&(i, w, max)| max - lengths[i] <= share(w));
+        if let Some(position) = capped {
+            let (i, _, max) = growing.remove(position);
+            remaining -= max - lengths[i];
+            lengths[i] = max;
+            continue;
+        }
+        let mut given = 0;
+        for &(i, w, _) in &growing {
+            lengths[i] += share(w);
+            given += share(w);
+        }
+        for &(i, _, _) in growing.iter().take(remaining - given) {
+            lengths[i] += 1;
+        }
+        remaining = 0;
+    }
+    lengths
+}
+
+#[test]
+fn test_intersection() {
+    let r = Rect::new(2, 2, 4, 4);
+    assert_eq!(r.intersection(Rect::new(0, 0, 3, 10)), Rect::new(2, 2, 1, 4));
+    assert_eq!(r.intersection(Rect::new(4, 5, 10, 10)), Rect::new(4, 5, 2, 1));
+    assert!(r.intersection(Rect::new(10, 0, 2, 2)).is_empty());
+}
+
+#[test]
+fn test_split() {
+    use self::Constraint::*;
+
+    let rect = Rect::new(2, 1, 10, 5);
+    assert_eq!(
+        rect.split(Direction::Horizontal, &[Length(3), Fill(1)]),
+        [Rect::new(2, 1, 3, 5), Rect::new(5, 1, 7, 5)]
+    );
+    assert_eq!(
+        rect.split(Direction::Vertical, &[Length(1), Min(1), Length(1)]),
+        [Rect::new(2, 1, 10, 1), Rect::new(2, 2, 10, 3), Rect::new(2, 5, 10, 1)]
+    );
+
+    // rounding is deterministic, and no cell is lost.
+    assert_eq!(solve(10, &[Ratio(1, 3), Ratio(1, 3), Ratio(1, 3)]), [4, 3, 3]);
+    assert_eq!(solve(10, &[Percentage(25), Percentage(75)]), [3, 7]);
+    assert_eq!(solve(10, &[Percentage(50), Ratio(0, 0)]), [5, 0]);
+    assert_eq!(solve(100, &[Percentage(33), Percentage(33)]), [33, 33]);
+
+    // later parts are shrunk if constraints don't fit.
+    assert_eq!(solve(10, &[Length(6), Min(6), Fill(1)]), [6, 4, 0]);
+
+    // the remaining space is shared by weights, respecting maximums.
+    assert_eq!(solve(10, &[Fill(1), Fill(2), Fill(0)]), [4, 6, 0]);
+    assert_eq!(solve(10, &[Max(2), Fill(1), Min(1)]), [2, 4, 4]);
+    assert_eq!(solve(10, &[Max(2), Length(3)]), [2, 3]);
+}
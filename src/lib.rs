@@ -49,18 +49,21 @@ extern crate num;
 extern crate num_derive;
 extern crate signal_notify;
 extern crate term;
+extern crate unicode_linebreak;
+extern crate unicode_segmentation;
 extern crate unicode_width;
 
 use std::io::prelude::*;
 use std::io::{self, BufWriter};
 use std::fs::{File, OpenOptions};
 use std::ops::Drop;
+use std::slice;
 use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 use std::os::unix::io::{AsRawFd, RawFd};
 
 use signal_notify::{notify, Signal};
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub mod key;
 mod event;
@@ -69,11 +72,23 @@ use screen::Screen;
 mod terminal;
 use terminal::Terminal;
 pub mod attr;
+pub mod border;
+pub mod layout;
+pub mod text;
+pub mod width;
+pub mod wrap;
+pub use width::DisplayWidth;
 
 use key::Key;
 pub use event::Event;
-pub use screen::Cell;
-use attr::Attribute;
+use event::{ColorSlot, Input, Reply};
+pub use screen::{Cell, CursorShape};
+pub use terminal::SynchronizedOutput;
+use attr::{Attribute, Color};
+use layout::{Direction, Rect};
+use border::BorderStyle;
+use text::{Line, Text};
+use wrap::{Align, Ellipsis};
 
 /// `Termfest` holds termfest states.
 /// It is created by `Termfest::hold`.
@@ -87,6 +102,9 @@ pub struct Termfest {
     /// `screen` should be guraded with `Mutex` because SIGWINCH watcher thread will modify width
     /// and height of `screen`
     screen: Arc<Mutex<Screen>>,
+    /// replies to queries like `background_color`, sent from the ttyin reader thread.
+    replies: Mutex<mpsc::Receiver<Reply>>,
+    synchronized_output: Mutex<SynchronizedOutput>,
 }
 
 impl Termfest {
@@ -113,12 +131,16 @@ impl Termfest {
 
         let terminal = Arc::new(Terminal::from_env()?);
         terminal.enter_ca(&mut ttyout)?;
+        terminal.push_title(&mut ttyout)?;
         terminal.enter_keypad(&mut ttyout)?;
         terminal.clear(&mut ttyout)?;
+        // the reply is handled by the ttyin reader.
+        terminal.query_mode(&mut ttyout, terminal::SYNCHRONIZED_OUTPUT_MODE)?;
 
         let (tx, rx) = mpsc::channel();
+        let (reply_tx, reply_rx) = mpsc::channel();
 
-        spawn_ttyin_reader(tx.clone(), terminal.clone())?;
+        spawn_ttyin_reader(tx.clone(), reply_tx, terminal.clone())?;
 
         let (width, height) = terminal::size(ttyout.as_raw_fd());
         let screen = Arc::new(Mutex::new(Screen::new(width, height)));
@@ -150,6 +172,8 @@ impl Termfest {
             orig_tios: orig_tios,
             terminal: terminal,
             screen: screen,
+            replies: Mutex::new(reply_rx),
+            synchronized_output: Mutex::new(SynchronizedOutput::Auto),
         };
         Ok((fest, rx))
     }
@@ -162,16 +186,140 @@ impl Termfest {
             screen: self.screen.lock().unwrap(),
             ttyout: &self.ttyout,
             terminal: &self.terminal,
+            synchronized_output: *self.synchronized_output.lock().unwrap(),
         }
     }
+
+    /// clears the terminal and repaints everything in the buffer.
+    /// It resynchronizes the terminal if other processes have written to it, like Ctrl-L in many
+    /// applications.
+    pub fn redraw_all(&self) -> io::Result<()> {
+        let mut screen = self.lock_screen();
+        screen.invalidate();
+        screen.flush()
+    }
+
+    /// configures how flushes are made atomic to avoid tearing.
+    /// The default is `SynchronizedOutput::Auto`.
+    pub fn set_synchronized_output(&self, mode: SynchronizedOutput) {
+        *self.synchronized_output.lock().unwrap() = mode;
+    }
+
+    /// sets the window title of the terminal.
+    /// The original title is restored when `self` is dropped.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// use termfest::Termfest;
+    /// let (fest, events) = Termfest::hold()?;
+    /// fest.set_title("building... 42%")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_title(&self, title: &str) -> io::Result<()> {
+        let mut ttyout = self.ttyout.lock().unwrap();
+        self.terminal.set_title(&mut *ttyout, title)?;
+        ttyout.flush()
+    }
+
+    /// queries the default foreground color of the terminal with OSC 10.
+    /// It returns `None` if the terminal doesn't reply within `timeout`, or replies in an
+    /// unsupported format.
+    pub fn foreground_color(&self, timeout: Duration) -> io::Result<Option<(u8, u8, u8)>> {
+        self.query_color(ColorSlot::Foreground, timeout)
+    }
+
+    /// queries the default background color of the terminal with OSC 11.
+    /// It returns `None` if the terminal doesn't reply within `timeout`, or replies in an
+    /// unsupported format.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// use std::time::Duration;
+    /// use termfest::Termfest;
+    /// let (fest, events) = Termfest::hold()?;
+    /// if let Some((r, g, b)) = fest.background_color(Duration::from_millis(100))? {
+    ///     // adapt the theme to the background.
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn background_color(&self, timeout: Duration) -> io::Result<Option<(u8, u8, u8)>> {
+        self.query_color(ColorSlot::Background, timeout)
+    }
+
+    /// queries the color of the palette entry `index` (used by `Color::EightBit(index)`) with
+    /// OSC 4.
+    /// It returns `None` if the terminal doesn't reply within `timeout`, or replies in an
+    /// unsupported format.
+    pub fn palette_color(&self, index: u8, timeout: Duration) -> io::Result<Option<(u8, u8, u8)>> {
+        self.query_color(ColorSlot::Palette(index), timeout)
+    }
+
+    /// returns whether the background color of the terminal is dark, based on its luma.
+    /// It returns `None` if the terminal doesn't reply within `timeout`, or replies in an
+    /// unsupported format.
+    pub fn is_dark_background(&self, timeout: Duration) -> io::Result<Option<bool>> {
+        Ok(self.background_color(timeout)?.map(|(r, g, b)| {
+            let luma = 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+            luma < 128 * 1000
+        }))
+    }
+
+    fn query_color(&self, slot: ColorSlot, timeout: Duration) -> io::Result<Option<(u8, u8, u8)>> {
+        let replies = self.replies.lock().unwrap();
+        // drop late replies to earlier queries that have timed out.
+        while replies.try_recv().is_ok() {}
+        {
+            let mut ttyout = self.ttyout.lock().unwrap();
+            self.terminal.query_color(&mut *ttyout, slot)?;
+            ttyout.flush()?;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if deadline <= now {
+                return Ok(None);
+            }
+            match replies.recv_timeout(deadline - now) {
+                Ok(Reply::Color(s, rgb)) if s == slot => return Ok(rgb),
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "ttyin reader has stopped",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// sets the icon name of the terminal, that is often shown in taskbars or tabs.
+    /// The original icon name is restored when `self` is dropped.
+    pub fn set_icon_name(&self, name: &str) -> io::Result<()> {
+        let mut ttyout = self.ttyout.lock().unwrap();
+        self.terminal.set_icon_name(&mut *ttyout, name)?;
+        ttyout.flush()
+    }
 }
 
 impl Drop for Termfest {
     fn drop(&mut self) {
         // ignore errors in drop
+        let styled_cursor = self.screen
+            .lock()
+            .map(|screen| screen.painted_cursor().is_styled())
+            .unwrap_or(true);
         if let Ok(mut ttyout) = self.ttyout.lock() {
+            if styled_cursor {
+                let _ = self.terminal
+                    .cursor_shape(&mut *ttyout, CursorShape::Default, false);
+                let _ = self.terminal.cursor_color(&mut *ttyout, Color::Default);
+            }
             let _ = self.terminal.show_cursor(&mut *ttyout);
             let _ = self.terminal.exit_keypad(&mut *ttyout);
+            let _ = self.terminal.pop_title(&mut *ttyout);
             let _ = self.terminal.exit_ca(&mut *ttyout);
             let _ = self.terminal.reset_attr(&mut *ttyout);
             unsafe {
@@ -189,6 +337,7 @@ pub struct ScreenLock<'a> {
     screen: MutexGuard<'a, Screen>,
     ttyout: &'a Mutex<BufWriter<File>>,
     terminal: &'a Terminal,
+    synchronized_output: SynchronizedOutput,
 }
 
 impl<'a> ScreenLock<'a> {
@@ -196,14 +345,29 @@ impl<'a> ScreenLock<'a> {
     /// Even if this function is not called, the buffer will be flushed when `self` is dropped.
     pub fn flush(&mut self) -> io::Result<()> {
         let mut ttyout = self.ttyout.lock().unwrap();
-        for command in self.screen.flush_commands() {
-            self.terminal.write(&mut *ttyout, command)?;
-        }
-        ttyout.flush()?;
+        let sync = self.terminal
+            .begin_synchronized_update(&mut *ttyout, self.synchronized_output)?;
+        let terminal = self.terminal;
+        let result = self.screen
+            .flush(terminal, |command| terminal.write(&mut *ttyout, command));
+        // the update is ended even if the flush fails, or the terminal stops showing output
+        // until it times out.
+        let ended = match sync {
+            Some(sync) => self.terminal.end_synchronized_update(&mut *ttyout, sync),
+            None => Ok(()),
+        };
+        let flushed = ttyout.flush();
+        result.and(ended).and(flushed)?;
         self.flushed = true;
         Ok(())
     }
 
+    /// forget the terminal state, so that the next flush clears the terminal and repaints
+    /// everything.
+    pub fn invalidate(&mut self) {
+        self.screen.invalidate();
+    }
+
     /// clear the internal buffer states.
     /// If clear and flush is called, the terminal will be cleared (nothing will be rendered).
     pub fn clear(&mut self) {
@@ -223,20 +387,193 @@ impl<'a> ScreenLock<'a> {
         self.screen.cursor.visible = true;
     }
 
+    /// set the shape of the cursor. `blink` is ignored for `CursorShape::Default`.
+    /// The terminal default is restored when `Termfest` is dropped.
+    pub fn set_cursor_shape(&mut self, shape: CursorShape, blink: bool) {
+        self.screen.cursor.shape = shape;
+        self.screen.cursor.blink = blink;
+    }
+
+    /// set the color of the cursor. `Color::Default` means the terminal default.
+    pub fn set_cursor_color(&mut self, color: Color) {
+        self.screen.cursor.color = color;
+    }
+
     /// print string with the given attribute.
-    /// It is equal to `put_cell` calls with each character.
+    /// It is equal to `put_cell` calls with each character, except that tabs are expanded to the
+    /// next tab stop. Other control characters are displayed as visible symbols like '\u{241b}'.
     pub fn print(&mut self, x: usize, y: usize, s: &str, attr: Attribute) {
         self.screen.print(x, y, s, attr)
     }
 
+    /// print string as a hyperlink to `url`.
+    /// Terminals supporting OSC 8 make the text clickable, and others render it as plain text.
+    pub fn print_link(&mut self, x: usize, y: usize, s: &str, url: &str, attr: Attribute) {
+        self.screen.print_link(x, y, s, url, attr)
+    }
+
+    /// print the spans of `line` one after another from (x, y), each with its own attribute.
+    /// It returns the number of columns consumed.
+    ///
+    /// ```no_run
+    /// use termfest::Termfest;
+    /// use termfest::text::Text;
+    ///
+    /// let (fest, _) = Termfest::hold().unwrap();
+    /// let mut screen = fest.lock_screen();
+    /// let text = Text::from_markup("[bold red]error[/]: not found");
+    /// screen.print_line(0, 0, &text.lines[0]);
+    /// ```
+    pub fn print_line(&mut self, x: usize, y: usize, line: &Line) -> usize {
+        let bounds = self.screen.bounds();
+        self.screen.print_line_in(bounds, x, y, line) - x
+    }
+
+    /// print `s` wrapped to the width of `rect`, breaking lines at Unicode line break
+    /// opportunities. It returns the number of rows consumed, which is at most `rect.height`.
+    pub fn print_wrapped(&mut self, rect: Rect, s: &str, attr: Attribute) -> usize {
+        let bounds = self.screen.bounds();
+        self.screen.print_wrapped_in(bounds, rect, s, attr)
+    }
+
+    /// print `s` in the first row of `rect`, truncated with '…' if it is wider than `rect` and
+    /// aligned otherwise. It returns the number of columns consumed.
+    ///
+    /// ```no_run
+    /// use termfest::Termfest;
+    /// use termfest::attr::Attribute;
+    /// use termfest::layout::Rect;
+    /// use termfest::wrap::{Align, Ellipsis};
+    ///
+    /// let (fest, _) = Termfest::hold().unwrap();
+    /// let mut screen = fest.lock_screen();
+    /// let path = "/home/user/projects/termfest/src/lib.rs";
+    /// let rect = Rect::new(0, 0, 20, 1);
+    /// screen.print_truncated(rect, path, Ellipsis::Middle, Align::Left, Attribute::default());
+    /// ```
+    pub fn print_truncated(
+        &mut self,
+        rect: Rect,
+        s: &str,
+        ellipsis: Ellipsis,
+        align: Align,
+        attr: Attribute,
+    ) -> usize {
+        let bounds = self.screen.bounds();
+        self.screen
+            .print_truncated_in(bounds, rect, s, ellipsis, align, attr)
+    }
+
+    /// print the lines of `text` from row `y`, and returns the number of rows consumed.
+    pub fn print_text(&mut self, x: usize, y: usize, text: &Text) -> usize {
+        for (i, line) in text.lines.iter().enumerate() {
+            self.print_line(x, y + i, line);
+        }
+        text.lines.len()
+    }
+
+    /// print `s` colored with SGR escape sequences, like the output of `ls --color`.
+    /// Other escape sequences are removed. See `Text::from_ansi` for details.
+    pub fn print_ansi(&mut self, x: usize, y: usize, s: &str) -> usize {
+        self.print_text(x, y, &Text::from_ansi(s))
+    }
+
     pub fn put_cell(&mut self, x: usize, y: usize, cell: Cell) {
         self.screen.put_cell(x, y, cell);
     }
 
+    /// returns the cell at (x, y) in the internal buffer, or `None` if it is out of the screen.
+    /// A cell of a grapheme cluster refers to the cluster stored in the screen until the next
+    /// flush. After that, putting the cell back shows only the first character of the cluster.
+    pub fn get_cell(&self, x: usize, y: usize) -> Option<Cell> {
+        self.screen.get_cell(x, y)
+    }
+
+    /// returns the grapheme cluster at (x, y) in the internal buffer, like "e\u{301}".
+    /// The right half of a wide character returns an empty string.
+    pub fn grapheme(&self, x: usize, y: usize) -> Option<String> {
+        self.screen.grapheme(x, y)
+    }
+
+    /// returns the cells of row `y` in the internal buffer.
+    pub fn row(&self, y: usize) -> Option<&[Cell]> {
+        self.screen.row(y)
+    }
+
+    /// returns an iterator over the rows of the internal buffer, from top to bottom.
+    pub fn rows(&self) -> slice::Chunks<'_, Cell> {
+        self.screen.rows()
+    }
+
+    /// returns the text in `rect` of the internal buffer, with trailing blanks of each row
+    /// trimmed and rows joined with newlines. The part of `rect` outside the screen is ignored.
+    pub fn text(&self, rect: Rect) -> String {
+        self.screen.text(rect)
+    }
+
+    /// set the distance between tab stops used by `print` (8 by default).
+    /// If `width` is 0, tabs are displayed as visible symbols instead.
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.screen.set_tab_width(width);
+    }
+
     /// returns the width and height of the terminal.
     pub fn size(&self) -> (usize, usize) {
         self.screen.size()
     }
+
+    /// draw a horizontal line of `length` cells from (x, y) to the right.
+    /// Lines already in the buffer are joined with junction characters like '┼'.
+    pub fn hline(
+        &mut self,
+        x: usize,
+        y: usize,
+        length: usize,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        let bounds = self.screen.bounds();
+        self.screen
+            .draw_line_in(bounds, (x, y), length, Direction::Horizontal, style, attr);
+    }
+
+    /// draw a vertical line of `length` cells from (x, y) to the bottom.
+    /// Lines already in the buffer are joined with junction characters like '┼'.
+    pub fn vline(
+        &mut self,
+        x: usize,
+        y: usize,
+        length: usize,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        let bounds = self.screen.bounds();
+        self.screen
+            .draw_line_in(bounds, (x, y), length, Direction::Vertical, style, attr);
+    }
+
+    /// draw the border of `rect` with an optional title on its top border.
+    /// Lines already in the buffer are joined with junction characters like '┬'.
+    pub fn draw_box(
+        &mut self,
+        rect: Rect,
+        style: BorderStyle,
+        title: Option<&str>,
+        attr: Attribute,
+    ) {
+        let bounds = self.screen.bounds();
+        self.screen.draw_box_in(bounds, rect, style, title, attr);
+    }
+
+    /// returns a view of `rect` of the screen.
+    /// The part of `rect` outside the screen is clipped.
+    pub fn region(&mut self, rect: Rect) -> Region<'_> {
+        let rect = rect.intersection(self.screen.bounds());
+        Region {
+            screen: &mut self.screen,
+            rect,
+        }
+    }
 }
 
 impl<'a> Drop for ScreenLock<'a> {
@@ -249,6 +586,175 @@ impl<'a> Drop for ScreenLock<'a> {
     }
 }
 
+/// `Region` is a view of a rectangular area of the screen, created by `ScreenLock::region`.
+/// Coordinates are relative to the top-left corner of the area, and everything drawn outside
+/// the area is clipped.
+pub struct Region<'a> {
+    screen: &'a mut Screen,
+    // the area in the screen coordinates, always inside the screen.
+    rect: Rect,
+}
+
+impl<'a> Region<'a> {
+    /// returns a view of `rect` of this region, in the coordinates of this region.
+    /// The part of `rect` outside this region is clipped.
+    pub fn region(&mut self, rect: Rect) -> Region<'_> {
+        let rect = self.translate_rect(rect);
+        Region {
+            screen: self.screen,
+            rect: rect.intersection(self.rect),
+        }
+    }
+
+    /// print string with the given attribute, like `ScreenLock::print`.
+    /// A wide character straddling the right edge is rendered as a blank.
+    pub fn print(&mut self, x: usize, y: usize, s: &str, attr: Attribute) {
+        if let Some((x, y)) = self.translate(x, y) {
+            self.screen.print_in(self.rect, x, y, s, attr, None);
+        }
+    }
+
+    /// print string as a hyperlink to `url`, like `ScreenLock::print_link`.
+    pub fn print_link(&mut self, x: usize, y: usize, s: &str, url: &str, attr: Attribute) {
+        if let Some((x, y)) = self.translate(x, y) {
+            self.screen.print_in(self.rect, x, y, s, attr, Some(url));
+        }
+    }
+
+    /// print the spans of `line`, like `ScreenLock::print_line`.
+    /// It returns the number of columns consumed, which may exceed the width of the region.
+    pub fn print_line(&mut self, x: usize, y: usize, line: &Line) -> usize {
+        match self.translate(x, y) {
+            Some((ax, ay)) => self.screen.print_line_in(self.rect, ax, ay, line) - ax,
+            None => 0,
+        }
+    }
+
+    /// print `s` wrapped to the width of `rect`, like `ScreenLock::print_wrapped`.
+    pub fn print_wrapped(&mut self, rect: Rect, s: &str, attr: Attribute) -> usize {
+        let rect = self.translate_rect(rect);
+        self.screen.print_wrapped_in(self.rect, rect, s, attr)
+    }
+
+    /// print `s` truncated to the width of `rect`, like `ScreenLock::print_truncated`.
+    pub fn print_truncated(
+        &mut self,
+        rect: Rect,
+        s: &str,
+        ellipsis: Ellipsis,
+        align: Align,
+        attr: Attribute,
+    ) -> usize {
+        let rect = self.translate_rect(rect);
+        self.screen
+            .print_truncated_in(self.rect, rect, s, ellipsis, align, attr)
+    }
+
+    /// print the lines of `text`, like `ScreenLock::print_text`.
+    pub fn print_text(&mut self, x: usize, y: usize, text: &Text) -> usize {
+        for (i, line) in text.lines.iter().enumerate() {
+            self.print_line(x, y + i, line);
+        }
+        text.lines.len()
+    }
+
+    /// print `s` colored with SGR escape sequences, like `ScreenLock::print_ansi`.
+    pub fn print_ansi(&mut self, x: usize, y: usize, s: &str) -> usize {
+        self.print_text(x, y, &Text::from_ansi(s))
+    }
+
+    pub fn put_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        if let Some((x, y)) = self.translate(x, y) {
+            self.screen.put_cell_in(self.rect, x, y, cell);
+        }
+    }
+
+    pub fn get_cell(&self, x: usize, y: usize) -> Option<Cell> {
+        self.translate(x, y)
+            .and_then(|(x, y)| self.screen.get_cell(x, y))
+    }
+
+    pub fn grapheme(&self, x: usize, y: usize) -> Option<String> {
+        self.translate(x, y)
+            .and_then(|(x, y)| self.screen.grapheme(x, y))
+    }
+
+    /// draw a horizontal line, like `ScreenLock::hline`.
+    pub fn hline(
+        &mut self,
+        x: usize,
+        y: usize,
+        length: usize,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        if let Some(start) = self.translate(x, y) {
+            let direction = Direction::Horizontal;
+            self.screen
+                .draw_line_in(self.rect, start, length, direction, style, attr);
+        }
+    }
+
+    /// draw a vertical line, like `ScreenLock::vline`.
+    pub fn vline(
+        &mut self,
+        x: usize,
+        y: usize,
+        length: usize,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        if let Some(start) = self.translate(x, y) {
+            let direction = Direction::Vertical;
+            self.screen
+                .draw_line_in(self.rect, start, length, direction, style, attr);
+        }
+    }
+
+    /// draw the border of `rect`, like `ScreenLock::draw_box`.
+    pub fn draw_box(
+        &mut self,
+        rect: Rect,
+        style: BorderStyle,
+        title: Option<&str>,
+        attr: Attribute,
+    ) {
+        let rect = self.translate_rect(rect);
+        self.screen.draw_box_in(self.rect, rect, style, title, attr);
+    }
+
+    /// fills the region with blank cells.
+    pub fn clear(&mut self) {
+        for y in self.rect.y..self.rect.bottom() {
+            for x in self.rect.x..self.rect.right() {
+                self.screen.put_cell_in(self.rect, x, y, Cell::default());
+            }
+        }
+    }
+
+    /// returns the width and height of the region.
+    pub fn size(&self) -> (usize, usize) {
+        (self.rect.width, self.rect.height)
+    }
+
+    /// returns the area of the region in the screen coordinates.
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    fn translate_rect(&self, rect: Rect) -> Rect {
+        Rect::new(self.rect.x + rect.x, self.rect.y + rect.y, rect.width, rect.height)
+    }
+
+    fn translate(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if x < self.rect.width && y < self.rect.height {
+            Some((self.rect.x + x, self.rect.y + y))
+        } else {
+            None
+        }
+    }
+}
+
 fn setup_tios(fd: ::libc::c_int) -> io::Result<libc::termios> {
     unsafe {
         let mut orig_tios: libc::termios = ::std::mem::uninitialized();
@@ -260,6 +766,8 @@ fn setup_tios(fd: ::libc::c_int) -> io::Result<libc::termios> {
             | libc::IGNCR | libc::ICRNL | libc::IXON);
         tios.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
         tios.c_cflag &= !(libc::CSIZE | libc::PARENB);
+        // line feeds are used to move the cursor down, keeping the column.
+        tios.c_oflag &= !libc::ONLCR;
         tios.c_cflag |= libc::CS8;
         tios.c_cc[libc::VMIN] = 1;
         tios.c_cc[libc::VTIME] = 0;
@@ -270,7 +778,11 @@ fn setup_tios(fd: ::libc::c_int) -> io::Result<libc::termios> {
     }
 }
 
-fn spawn_ttyin_reader(tx: mpsc::Sender<Event>, term: Arc<Terminal>) -> io::Result<()> {
+fn spawn_ttyin_reader(
+    tx: mpsc::Sender<Event>,
+    reply_tx: mpsc::Sender<Reply>,
+    term: Arc<Terminal>,
+) -> io::Result<()> {
     let mut ttyin = OpenOptions::new()
         .write(false)
         .read(true)
@@ -297,55 +809,76 @@ fn spawn_ttyin_reader(tx: mpsc::Sender<Event>, term: Arc<Terminal>) -> io::Resul
     let sigio = notify(&[Signal::IO]);
     ::std::thread::spawn(move || {
         let mut buf = Vec::new();
-        for _ in sigio.iter() {
-            let mut tmpbuf = [0; 64];
-            match ttyin.read(&mut tmpbuf) {
-                Ok(n) => buf.extend(&tmpbuf[..n]),
-                Err(e) => match e.kind() {
-                    io::ErrorKind::WouldBlock | io::ErrorKind::InvalidInput => continue,
-                    _ => panic!("failed to read from tty: {}", e),
-                },
+        // true if `buf` may be a prefix of a reply, waiting for the rest.
+        let mut waiting = false;
+        loop {
+            let timed_out = if waiting {
+                match sigio.recv_timeout(event::REPLY_TIMEOUT) {
+                    Ok(_) => false,
+                    Err(mpsc::RecvTimeoutError::Timeout) => true,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                if sigio.recv().is_err() {
+                    break;
+                }
+                false
             };
+            if !timed_out {
+                let mut tmpbuf = [0; 64];
+                match ttyin.read(&mut tmpbuf) {
+                    Ok(n) => buf.extend(&tmpbuf[..n]),
+                    Err(e) => match e.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::InvalidInput => continue,
+                        _ => panic!("failed to read from tty: {}", e),
+                    },
+                };
+            }
             let mut from = 0;
             loop {
-                if let Some((read_byte, ev)) = event::parse(&buf[from..], &*term) {
+                if let Some((read_byte, input)) = event::parse(&buf[from..], &*term, timed_out) {
                     from += read_byte;
-                    if tx.send(ev).is_err() {
-                        break;
+                    match input {
+                        Input::Event(ev) => if tx.send(ev).is_err() {
+                            break;
+                        },
+                        Input::Reply(reply) => {
+                            match reply {
+                                Reply::Mode { mode, status } => term.set_mode_status(mode, status),
+                                Reply::Color(..) => term.color_replied(),
+                            }
+                            // nobody may be waiting for the reply.
+                            let _ = reply_tx.send(reply);
+                        }
                     }
                 } else {
                     break;
                 }
             }
             buf = buf[from..].to_vec();
+            // bytes left after a timeout are an incomplete character, which is not a reply.
+            waiting = !buf.is_empty() && !timed_out;
         }
     });
     Ok(())
 }
 
-/// `DisplayWidth` provides a way to determine display width of characters or strings.
-///
-/// ```
-/// use termfest::DisplayWidth;
-///
-/// assert_eq!('あ'.display_width(), 2);
-/// assert_eq!('a'.display_width(), 1);
-///
-/// assert_eq!("abc".display_width(), 3);
-/// assert_eq!("あいう".display_width(), 6);
-/// ```
-pub trait DisplayWidth {
-    fn display_width(&self) -> usize;
-}
-
-impl DisplayWidth for char {
-    fn display_width(&self) -> usize {
-        self.width().unwrap_or(1)
-    }
-}
-
-impl DisplayWidth for str {
-    fn display_width(&self) -> usize {
-        self.width()
+#[test]
+fn test_region() {
+    let mut screen = Screen::new(10, 4);
+    {
+        let mut region = Region {
+            screen: &mut screen,
+            rect: Rect::new(2, 1, 5, 2),
+        };
+        assert_eq!(region.size(), (5, 2));
+        region.print(0, 0, "abcdefg", Attribute::default());
+        // the wide character straddling the right edge is rendered as a blank.
+        region.print(0, 1, "xyあいう", Attribute::default());
+        let mut inner = region.region(Rect::new(3, 1, 10, 10));
+        assert_eq!(inner.rect(), Rect::new(5, 2, 2, 1));
+        inner.put_cell(1, 0, Cell::new('z'));
+        inner.put_cell(2, 0, Cell::new('!'));
     }
+    assert_eq!(screen.text(screen.bounds()), "\n  abcde\n  xyあz\n");
 }
@@ -1,31 +1,176 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::default::Default;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::mem;
+use std::slice::Chunks;
+use std::num::NonZeroU32;
 
-use terminal::Command;
+use unicode_segmentation::UnicodeSegmentation;
+
+use terminal::{Command, Terminal};
 use attr::{Attribute, Color, Effect};
-use super::DisplayWidth;
+use layout::{Direction, Rect};
+use border::{self, BorderStyle};
+use text::Line;
+use wrap::{self, Align, Ellipsis};
+use width::{self, grapheme_width, DisplayWidth};
+
+/// `Grapheme` is what a cell displays: an extended grapheme cluster like "a", "e\u{301}" or
+/// "\u{1f44d}\u{1f3fd}".
+/// Single characters, that are the common case, are stored inline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Grapheme {
+    Char(char),
+    /// a cluster of multiple characters, starting with `.0`. `.1` is its id in `Screen::clusters`.
+    Cluster(char, ClusterId),
+    /// the right half of the wide character in the previous cell.
+    Continuation,
+}
+
+type ClusterId = NonZeroU32;
+
+impl Grapheme {
+    fn new(g: &str, clusters: &mut StringTable) -> Grapheme {
+        let mut chars = g.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Grapheme::Char(visible(ch)),
+            // a cluster starting with a control character is a control character or CR LF.
+            (Some(ch), _) if visible(ch) != ch => Grapheme::Char(visible(ch)),
+            (Some(ch), _) => Grapheme::Cluster(ch, clusters.id(g)),
+            (None, _) => Grapheme::Char(' '),
+        }
+    }
+
+    fn width(&self, clusters: &StringTable) -> usize {
+        match *self {
+            Grapheme::Char(ch) => ch.display_width(),
+            Grapheme::Cluster(_, id) => grapheme_width(clusters.get(id)),
+            Grapheme::Continuation => 0,
+        }
+    }
+
+    fn len(&self, clusters: &StringTable) -> usize {
+        match *self {
+            Grapheme::Char(ch) => ch.len_utf8(),
+            Grapheme::Cluster(_, id) => clusters.get(id).len(),
+            Grapheme::Continuation => 0,
+        }
+    }
+
+    fn push_to(&self, s: &mut String, clusters: &StringTable) {
+        match *self {
+            Grapheme::Char(ch) => s.push(ch),
+            Grapheme::Cluster(_, id) => s.push_str(clusters.get(id)),
+            Grapheme::Continuation => {}
+        }
+    }
+}
+
+/// `StringTable` assigns ids to strings referenced by cells, so that `Cell` stays small and
+/// `Copy`. `strings[id - 1]` is the string of `id`, or `None` if the id is released.
+#[derive(Debug, Clone, Default)]
+struct StringTable {
+    strings: Vec<Option<String>>,
+    ids: HashMap<String, NonZeroU32>,
+    // true if strings are added since the last release.
+    added: bool,
+}
+
+impl StringTable {
+    /// returns the id of `s`, adding it if it is not in the table yet.
+    fn id(&mut self, s: &str) -> NonZeroU32 {
+        if let Some(&id) = self.ids.get(s) {
+            return id;
+        }
+        let index = match self.strings.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.strings.push(None);
+                self.strings.len() - 1
+            }
+        };
+        let id = NonZeroU32::new(index as u32 + 1).unwrap();
+        self.strings[index] = Some(s.to_string());
+        self.ids.insert(s.to_string(), id);
+        self.added = true;
+        id
+    }
+
+    fn get(&self, id: NonZeroU32) -> &str {
+        self.strings[id.get() as usize - 1].as_ref().unwrap()
+    }
+
+    fn contains(&self, id: NonZeroU32) -> bool {
+        self.strings.get(id.get() as usize - 1).is_some_and(Option::is_some)
+    }
+
+    /// releases the strings whose ids are not in `used`, if any string is added since the last
+    /// release, so that the table does not grow with every string ever added.
+    fn release<I: Iterator<Item = NonZeroU32>>(&mut self, used: I) {
+        if !self.added {
+            return;
+        }
+        self.added = false;
+        let mut marks = vec![false; self.strings.len()];
+        for id in used {
+            marks[id.get() as usize - 1] = true;
+        }
+        for (s, &used) in self.strings.iter_mut().zip(&marks) {
+            if !used {
+                *s = None;
+            }
+        }
+        self.ids.retain(|_, id| marks[id.get() as usize - 1]);
+        while let Some(&None) = self.strings.last() {
+            self.strings.pop();
+        }
+    }
+}
+
+/// returns a printable replacement of control characters, so that they are never written to the
+/// terminal as they are. C0 controls and DEL are shown as control pictures like '\u{241b}'.
+fn visible(ch: char) -> char {
+    match ch {
+        '\x00'..='\x1f' => ::std::char::from_u32(0x2400 + ch as u32).unwrap(),
+        '\x7f' => '\u{2421}',
+        '\u{80}'..='\u{9f}' => '\u{fffd}',
+        _ => ch,
+    }
+}
 
 /// `Cell` is a cell of the terminal.
-/// It has a display character and an attribute (fg and bg color, effects).
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// It has a display character (or a grapheme cluster) and an attribute (fg and bg color, effects).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Cell {
-    ch: char,
+    ch: Grapheme,
     attribute: Attribute,
+    // id in `Screen::links`, so that a cell without a hyperlink costs only a few bytes.
+    link: Option<LinkId>,
 }
 
+/// `LinkId` identifies a hyperlink target registered in a `Screen`.
+pub type LinkId = NonZeroU32;
+
 impl Default for Cell {
     fn default() -> Self {
         Cell {
-            ch: ' ',
+            ch: Grapheme::Char(' '),
             attribute: Attribute::default(),
+            link: None,
         }
     }
 }
 
 impl Cell {
+    /// creates a cell that displays `ch`. Control characters are displayed as visible symbols.
     pub fn new(ch: char) -> Self {
         Cell {
-            ch: ch,
+            ch: Grapheme::Char(visible(ch)),
             attribute: Attribute::default(),
+            link: None,
         }
     }
 
@@ -48,6 +193,35 @@ impl Cell {
         self.attribute = attr;
         self
     }
+
+    /// returns the first character of the displayed grapheme cluster.
+    /// The right half of a wide character returns ' '.
+    pub fn ch(&self) -> char {
+        match self.ch {
+            Grapheme::Char(ch) => ch,
+            Grapheme::Cluster(ch, _) => ch,
+            Grapheme::Continuation => ' ',
+        }
+    }
+
+    pub fn attr(&self) -> Attribute {
+        self.attribute
+    }
+
+    /// returns true if the cell is the right half of the wide character in the previous cell.
+    pub fn is_continuation(&self) -> bool {
+        self.ch == Grapheme::Continuation
+    }
+}
+
+/// `CursorShape` is a shape of the terminal cursor.
+/// `Default` leaves the shape to the terminal's configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Default,
+    Block,
+    Underline,
+    Bar,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -55,6 +229,30 @@ pub struct Cursor {
     pub x: usize,
     pub y: usize,
     pub visible: bool,
+    pub shape: CursorShape,
+    pub blink: bool,
+    /// `Color::Default` leaves the color to the terminal's configuration.
+    pub color: Color,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor {
+            x: 0,
+            y: 0,
+            visible: true,
+            shape: CursorShape::Default,
+            blink: false,
+            color: Color::Default,
+        }
+    }
+}
+
+impl Cursor {
+    /// returns true if the shape or the color of the cursor differs from the terminal default.
+    pub fn is_styled(&self) -> bool {
+        self.shape != CursorShape::Default || self.color != Color::Default
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -63,11 +261,32 @@ pub struct Screen {
     pub height: usize,
     // length of `cells` is `width * height`
     // accessing (x, y) is equal to `cells[x + y * width]`
-    pub cells: Vec<Cell>,
+    cells: Vec<Cell>,
     pub cursor: Cursor,
+    // `dirty[y]` is true if row `y` of `cells` may have been modified since the last flush.
+    dirty: Vec<bool>,
 
     painted_cells: Vec<Cell>,
     painted_cursor: Cursor,
+    // the position of the terminal's cursor, if known.
+    painted_position: Option<(usize, usize)>,
+    // true if the terminal should be cleared before the next flush.
+    needs_clear: bool,
+    // the distance between tab stops, or 0 to show tabs as control characters.
+    tab_width: usize,
+    // `width::policy_generation()` when `cells` were laid out.
+    width_generation: usize,
+
+    // buffers reused across flushes.
+    text: String,
+    new_hashes: Vec<u64>,
+    old_hashes: Vec<u64>,
+
+    // hyperlink targets referenced by `Cell::link`, and grapheme clusters referenced by
+    // `Grapheme::Cluster`. Strings no longer referenced by `cells` or `painted_cells` are
+    // released on flush, and their ids are reused.
+    links: StringTable,
+    clusters: StringTable,
 }
 
 impl Screen {
@@ -76,24 +295,27 @@ impl Screen {
             width: width,
             height: height,
             cells: vec![Cell::default(); width * height],
-            cursor: Cursor {
-                x: 0,
-                y: 0,
-                visible: true,
-            },
+            dirty: vec![true; height],
+            cursor: Cursor::default(),
 
             painted_cells: vec![Cell::default(); width * height],
-            painted_cursor: Cursor {
-                x: 0,
-                y: 0,
-                visible: true,
-            },
+            painted_cursor: Cursor::default(),
+            painted_position: None,
+            needs_clear: false,
+            tab_width: 8,
+            width_generation: width::policy_generation(),
+
+            text: String::new(),
+            new_hashes: Vec::new(),
+            old_hashes: Vec::new(),
+
+            links: StringTable::default(),
+            clusters: StringTable::default(),
         }
     }
 
     fn copy_cells(&self, original: &[Cell], width: usize, height: usize) -> Vec<Cell> {
         let mut new_cells = vec![Cell::default(); width * height];
-        use std::cmp;
         let min_height = cmp::min(height, self.height);
         let min_width = cmp::min(width, self.width);
         for y in 0..min_height {
@@ -102,6 +324,10 @@ impl Screen {
             let start = y * width;
             let end = min_width + start;
             (&mut new_cells[start..end]).copy_from_slice(&original[orig_start..orig_end]);
+            // a wide character cut by the new right edge can no longer be displayed.
+            if min_width > 0 && new_cells[end - 1].ch.width(&self.clusters) == 2 {
+                new_cells[end - 1].ch = Grapheme::Char(' ');
+            }
         }
         new_cells
     }
@@ -111,13 +337,127 @@ impl Screen {
         self.painted_cells = self.copy_cells(&self.painted_cells, width, height);
         self.width = width;
         self.height = height;
+        self.dirty = vec![true; height];
+        // terminals may move the cursor when they are resized.
+        self.painted_position = None;
     }
 
     pub fn clear(&mut self) {
         for cell in self.cells.iter_mut() {
-            cell.ch = ' ';
-            cell.attribute = Attribute::default();
+            *cell = Cell::default();
         }
+        for dirty in self.dirty.iter_mut() {
+            *dirty = true;
+        }
+    }
+
+    /// forgets the terminal state, so that the next flush clears the terminal and repaints
+    /// everything. It is useful if the terminal was modified by other processes.
+    pub fn invalidate(&mut self) {
+        self.needs_clear = true;
+        for cell in self.painted_cells.iter_mut() {
+            *cell = Cell::default();
+        }
+        for dirty in self.dirty.iter_mut() {
+            *dirty = true;
+        }
+        self.painted_position = None;
+    }
+
+    /// blanks characters whose width has changed with the width policy, so that every wide
+    /// character is followed by its continuation cell and vice versa. If any character is
+    /// blanked, the terminal, which displays it with the previous width, is repainted.
+    fn relayout(&mut self) {
+        let mut changed = false;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = x + y * self.width;
+                let fits = match self.cells[i].ch {
+                    Grapheme::Continuation => {
+                        x > 0 && self.cells[i - 1].ch.width(&self.clusters) == 2
+                    }
+                    ch if ch.width(&self.clusters) == 2 => {
+                        x + 1 < self.width && self.cells[i + 1].ch == Grapheme::Continuation
+                    }
+                    _ => true,
+                };
+                if !fits {
+                    self.cells[i].ch = Grapheme::Char(' ');
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.invalidate();
+        }
+    }
+
+    /// returns the id of the hyperlink target `url`, registering it if it is not known yet.
+    pub fn link_id(&mut self, url: &str) -> LinkId {
+        self.links.id(&sanitize_url(url))
+    }
+
+    /// releases hyperlink targets and grapheme clusters referenced neither by the buffer nor by
+    /// the terminal.
+    fn release_strings(&mut self) {
+        let (buffer, painted) = (&self.cells, &self.painted_cells);
+        let cells = || buffer.iter().chain(painted);
+        self.links.release(cells().filter_map(|cell| cell.link));
+        self.clusters.release(cells().filter_map(|cell| match cell.ch {
+            Grapheme::Cluster(_, id) => Some(id),
+            _ => None,
+        }));
+    }
+
+    pub fn get_cell(&self, x: usize, y: usize) -> Option<Cell> {
+        self.index(x, y).map(|i| self.cells[i])
+    }
+
+    pub fn row(&self, y: usize) -> Option<&[Cell]> {
+        if y < self.height {
+            Some(&self.cells[y * self.width..(y + 1) * self.width])
+        } else {
+            None
+        }
+    }
+
+    pub fn rows(&self) -> Chunks<'_, Cell> {
+        self.cells.chunks(self.width.max(1))
+    }
+
+    /// returns the grapheme cluster displayed at (x, y).
+    /// The right half of a wide character returns an empty string.
+    pub fn grapheme(&self, x: usize, y: usize) -> Option<String> {
+        self.index(x, y).map(|i| {
+            let mut s = String::new();
+            self.cells[i].ch.push_to(&mut s, &self.clusters);
+            s
+        })
+    }
+
+    /// returns the text in `rect`, skipping the right halves of wide characters and trimming
+    /// trailing blanks of each row. Rows are joined with newlines.
+    pub fn text(&self, rect: Rect) -> String {
+        let rect = rect.intersection(Rect::new(0, 0, self.width, self.height));
+        let mut text = String::new();
+        if rect.is_empty() {
+            return text;
+        }
+        for y in rect.y..rect.bottom() {
+            if y > rect.y {
+                text.push('\n');
+            }
+            for cell in &self.cells[self.index(rect.x, y).unwrap()..][..rect.width] {
+                cell.ch.push_to(&mut text, &self.clusters);
+            }
+            let len = text.trim_end_matches(' ').len();
+            text.truncate(len);
+        }
+        text
+    }
+
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width;
     }
 
     fn index(&self, x: usize, y: usize) -> Option<usize> {
@@ -128,84 +468,1062 @@ impl Screen {
         }
     }
 
-    pub fn print(&mut self, mut x: usize, y: usize, s: &str, attr: Attribute) {
+    /// returns the area of the whole screen.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn print(&mut self, x: usize, y: usize, s: &str, attr: Attribute) {
+        let bounds = self.bounds();
+        self.print_in(bounds, x, y, s, attr, None);
+    }
+
+    pub fn print_link(&mut self, x: usize, y: usize, s: &str, url: &str, attr: Attribute) {
+        let bounds = self.bounds();
+        self.print_in(bounds, x, y, s, attr, Some(url));
+    }
+
+    /// prints `s` at (x, y) like `print`, but cells outside `clip` are left untouched.
+    /// Tab stops are counted from the left edge of `clip`.
+    /// It returns the x coordinate next to the printed string.
+    pub fn print_in(
+        &mut self,
+        clip: Rect,
+        mut x: usize,
+        y: usize,
+        s: &str,
+        attr: Attribute,
+        url: Option<&str>,
+    ) -> usize {
+        let clip = clip.intersection(self.bounds());
         let mut cell = Cell {
             attribute: attr,
             ..Cell::default()
         };
-        for c in s.chars() {
-            cell.ch = c;
-            self.put_cell(x, y, cell);
-            x += c.display_width();
+        for g in s.graphemes(true) {
+            if x >= clip.right() {
+                return x;
+            }
+            if g == "\t" && self.tab_width > 0 {
+                cell.ch = Grapheme::Char(' ');
+                let column = x.saturating_sub(clip.x);
+                let stop = clip.x + (column / self.tab_width + 1) * self.tab_width;
+                while x < stop && x < clip.right() {
+                    self.put_link_cell(clip, x, y, &mut cell, url);
+                    x += 1;
+                }
+                x = stop;
+                continue;
+            }
+            if g.starts_with(|ch| visible(ch) != ch) {
+                // each character of CR LF is shown in its own cell.
+                for ch in g.chars() {
+                    cell.ch = Grapheme::Char(visible(ch));
+                    self.put_link_cell(clip, x, y, &mut cell, url);
+                    x += cell.ch.width(&self.clusters);
+                }
+                continue;
+            }
+            let width = grapheme_width(g);
+            // zero-width clusters like a lone combining mark can't be rendered in their own cell.
+            if width == 0 {
+                continue;
+            }
+            cell.ch = Grapheme::new(g, &mut self.clusters);
+            self.put_link_cell(clip, x, y, &mut cell, url);
+            x += width;
+        }
+        x
+    }
+
+    /// puts `cell` like `put_cell_in`, linking it to `url`. The url is registered when the first
+    /// cell is put in `clip`, so that text clipped entirely registers nothing.
+    fn put_link_cell(
+        &mut self,
+        clip: Rect,
+        x: usize,
+        y: usize,
+        cell: &mut Cell,
+        url: Option<&str>,
+    ) {
+        if cell.link.is_none() && clip.contains(x, y) {
+            cell.link = url.map(|url| self.link_id(url));
+        }
+        self.put_cell_in(clip, x, y, *cell);
+    }
+
+    /// prints `s` wrapped to the width of `rect`, and returns the number of rows used.
+    /// Lines beyond the height of `rect` are not printed.
+    pub fn print_wrapped_in(&mut self, clip: Rect, rect: Rect, s: &str, attr: Attribute) -> usize {
+        let clip = clip.intersection(rect);
+        let lines = wrap::wrap(s, rect.width, self.tab_width);
+        let rows = cmp::min(lines.len(), rect.height);
+        for (i, line) in lines.into_iter().take(rows).enumerate() {
+            self.print_in(clip, rect.x, rect.y + i, line, attr, None);
+        }
+        rows
+    }
+
+    /// prints `s` truncated to the width of `rect` in its first row, and returns the number of
+    /// columns used.
+    pub fn print_truncated_in(
+        &mut self,
+        clip: Rect,
+        rect: Rect,
+        s: &str,
+        ellipsis: Ellipsis,
+        align: Align,
+        attr: Attribute,
+    ) -> usize {
+        if rect.is_empty() {
+            return 0;
+        }
+        let clip = clip.intersection(rect);
+        // tabs are expanded first, as a cut can change the columns of tabs after it.
+        let s = wrap::truncate(&wrap::expand_tabs(s, self.tab_width), rect.width, ellipsis);
+        let width = s.display_width();
+        let x = rect.x + align.offset(width, rect.width);
+        self.print_in(clip, x, rect.y, &s, attr, None);
+        width
+    }
+
+    /// prints the spans of `line` one after another from (x, y), and returns the x coordinate
+    /// next to them.
+    pub fn print_line_in(&mut self, clip: Rect, mut x: usize, y: usize, line: &Line) -> usize {
+        for span in &line.spans {
+            x = self.print_in(clip, x, y, &span.content, span.attr, None);
         }
+        x
     }
 
+    /// puts `cell` at (x, y).
+    /// a wide character occupies (x, y) and (x + 1, y), and overwriting either half of it blanks
+    /// the other half. a wide character which does not fit in the last column is put as a blank.
+    /// a zero-width character like a lone combining mark is ignored, as terminals join it to
+    /// the previous character instead of displaying it in its own cell.
     pub fn put_cell(&mut self, x: usize, y: usize, cell: Cell) {
-        if let Some(i) = self.index(x, y) {
-            self.cells[i] = cell;
+        let bounds = self.bounds();
+        self.put_cell_in(bounds, x, y, cell);
+    }
+
+    /// puts `cell` at (x, y) like `put_cell` if (x, y) is in `clip`.
+    /// a wide character which does not fit in `clip` is put as a blank.
+    pub fn put_cell_in(&mut self, clip: Rect, x: usize, y: usize, mut cell: Cell) {
+        let clip = clip.intersection(self.bounds());
+        if !clip.contains(x, y) {
+            return;
+        }
+        // a cell read before a flush may refer to a released cluster or hyperlink target.
+        if let Grapheme::Cluster(ch, id) = cell.ch {
+            if !self.clusters.contains(id) {
+                cell.ch = Grapheme::Char(ch);
+            }
+        }
+        if cell.link.is_some_and(|id| !self.links.contains(id)) {
+            cell.link = None;
+        }
+        // continuation cells are zero-width too, and are put only with their wide character.
+        let width = cell.ch.width(&self.clusters);
+        if width == 0 {
+            return;
+        }
+        let i = x + y * self.width;
+        if width == 2 && x + 1 == clip.right() {
+            cell.ch = Grapheme::Char(' ');
+        }
+        self.dirty[y] = true;
+        self.break_wide(x, y);
+        self.cells[i] = cell;
+        if cell.ch.width(&self.clusters) == 2 {
+            self.break_wide(x + 1, y);
+            self.cells[i + 1] = Cell {
+                ch: Grapheme::Continuation,
+                ..cell
+            };
         }
     }
 
-    pub fn flush_commands(&mut self) -> Vec<Command> {
-        let mut commands = Vec::new();
-        let mut last_x = !0;
-        let mut last_y = !0;
+    /// draws a line of `length` cells from `start` to the right or to the bottom, merging it
+    /// with lines already in the buffer. An end of the line put on another line becomes a
+    /// junction like '├'.
+    pub fn draw_line_in(
+        &mut self,
+        clip: Rect,
+        start: (usize, usize),
+        length: usize,
+        direction: Direction,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        let (axis, head, tail) = match direction {
+            Direction::Horizontal => (border::HORIZONTAL, border::LEFT, border::RIGHT),
+            Direction::Vertical => (border::VERTICAL, border::UP, border::DOWN),
+        };
+        for i in 0..length {
+            let (x, y) = match direction {
+                Direction::Horizontal => (start.0 + i, start.1),
+                Direction::Vertical => (start.0, start.1 + i),
+            };
+            let mut arms = axis;
+            let on_line = self.get_cell(x, y)
+                .is_some_and(|cell| border::is_line(cell.ch()));
+            if length > 1 && on_line {
+                if i == 0 {
+                    arms[head] = 0;
+                } else if i == length - 1 {
+                    arms[tail] = 0;
+                }
+            }
+            self.put_arms(clip, x, y, arms, style, attr);
+        }
+    }
+
+    /// draws the border of `rect`, merging it with lines already in the buffer.
+    /// `title` is printed on the top border.
+    pub fn draw_box_in(
+        &mut self,
+        clip: Rect,
+        rect: Rect,
+        style: BorderStyle,
+        title: Option<&str>,
+        attr: Attribute,
+    ) {
+        if rect.is_empty() {
+            return;
+        }
+        // a box one cell high or wide is drawn as a line.
+        let start = (rect.x, rect.y);
+        if rect.height == 1 {
+            self.draw_line_in(clip, start, rect.width, Direction::Horizontal, style, attr);
+            return;
+        }
+        if rect.width == 1 {
+            self.draw_line_in(clip, start, rect.height, Direction::Vertical, style, attr);
+            return;
+        }
+        let (left, top, right, bottom) = (rect.x, rect.y, rect.right() - 1, rect.bottom() - 1);
+        for x in left + 1..right {
+            self.put_arms(clip, x, top, border::HORIZONTAL, style, attr);
+            self.put_arms(clip, x, bottom, border::HORIZONTAL, style, attr);
+        }
+        for y in top + 1..bottom {
+            self.put_arms(clip, left, y, border::VERTICAL, style, attr);
+            self.put_arms(clip, right, y, border::VERTICAL, style, attr);
+        }
+        self.put_arms(clip, left, top, [0, 1, 0, 1], style, attr);
+        self.put_arms(clip, right, top, [0, 1, 1, 0], style, attr);
+        self.put_arms(clip, left, bottom, [1, 0, 0, 1], style, attr);
+        self.put_arms(clip, right, bottom, [1, 0, 1, 0], style, attr);
+        if let Some(title) = title {
+            let area = Rect::new(left + 1, top, rect.width - 2, 1).intersection(clip);
+            self.print_in(area, left + 1, top, title, attr, None);
+        }
+    }
+
+    fn put_arms(
+        &mut self,
+        clip: Rect,
+        x: usize,
+        y: usize,
+        arms: border::Arms,
+        style: BorderStyle,
+        attr: Attribute,
+    ) {
+        if let Some(cell) = self.get_cell(x, y) {
+            let ch = border::merge(cell.ch(), arms, style);
+            self.put_cell_in(clip, x, y, Cell::new(ch).attribute(attr));
+        }
+    }
+
+    /// blanks the other half of the wide character covering (x, y), if any.
+    fn break_wide(&mut self, x: usize, y: usize) {
+        let i = y * self.width + x;
+        match self.cells[i].ch {
+            Grapheme::Continuation if x > 0 => self.cells[i - 1].ch = Grapheme::Char(' '),
+            ch if ch.width(&self.clusters) == 2 && x + 1 < self.width => {
+                self.cells[i + 1].ch = Grapheme::Char(' ')
+            }
+            _ => {}
+        }
+    }
+
+    /// flushes the difference between the buffer and the terminal state, passing commands to
+    /// `emit` in order. Consecutive characters are batched into a single `Command::PutStr`.
+    pub fn flush<F>(&mut self, terminal: &Terminal, emit: F) -> io::Result<()>
+    where
+        F: FnMut(Command) -> io::Result<()>,
+    {
+        // `text` is taken from `self` to reuse its allocation across flushes.
+        let text = mem::take(&mut self.text);
+        let mut painter = Painter { text, emit };
+        let result = self.paint(terminal, &mut painter);
+        self.text = painter.text;
+        self.text.clear();
+        self.release_strings();
+        result
+    }
+
+    fn paint<F>(&mut self, terminal: &Terminal, painter: &mut Painter<F>) -> io::Result<()>
+    where
+        F: FnMut(Command) -> io::Result<()>,
+    {
+        let generation = width::policy_generation();
+        if generation != self.width_generation {
+            self.relayout();
+            self.width_generation = generation;
+        }
+        let mut pos = self.painted_position;
+        // the cursor motion, computed once to be compared with re-printing and to be written.
+        let mut motion = Vec::new();
         let mut last_attr = Attribute::default();
-        commands.push(Command::ResetAttr);
+        let mut last_link = None;
+        painter.command(Command::ResetAttr)?;
+        if self.needs_clear {
+            painter.command(Command::Clear)?;
+            self.needs_clear = false;
+        }
+        // applications often redraw everything, so dirty rows are usually unchanged.
+        let mut changed_rows = 0;
         for y in 0..self.height {
-            let mut last_is_multiwidth = false;
-            for x in 0..self.width {
+            let row = y * self.width..(y + 1) * self.width;
+            if self.dirty[y] && self.cells[row.clone()] == self.painted_cells[row] {
+                self.dirty[y] = false;
+            }
+            if self.dirty[y] {
+                changed_rows += 1;
+            }
+        }
+        // scrolling pays off only if it saves repainting at least two rows.
+        if changed_rows >= 2 && terminal.can_scroll() {
+            if let Some((top, bottom, shift)) = self.find_scroll() {
+                self.scroll(painter, top, bottom, shift)?;
+                // setting a scroll region moves the cursor on most terminals.
+                pos = None;
+            }
+        }
+        for y in 0..self.height {
+            if !self.dirty[y] {
+                continue;
+            }
+            self.dirty[y] = false;
+            let row_end = (y + 1) * self.width;
+            let mut x = 0;
+            while x < self.width {
                 let index = self.index(x, y).unwrap();
-                if last_is_multiwidth {
-                    last_is_multiwidth = false;
-                    let leftcell = self.cells[index - 1];
-                    self.painted_cells[index] = Cell {
-                        ch: ' ',
-                        ..leftcell
-                    };
+                if self.painted_cells[index] == self.cells[index] {
+                    x += 1;
                     continue;
                 }
-                if self.painted_cells[index] == self.cells[index] {
+                if self.cells[index].ch == Grapheme::Continuation {
+                    // covered by the wide character on its left, which is unchanged.
+                    self.painted_cells[index] = self.cells[index];
+                    x += 1;
                     continue;
                 }
-                let cell = &mut self.cells[index];
-                if cell.attribute != last_attr {
-                    commands.push(Command::ResetAttr);
-                    commands.push(Command::Fg(cell.attribute.fg));
-                    commands.push(Command::Bg(cell.attribute.bg));
-                    commands.push(Command::Effect(cell.attribute.effect));
-                    last_attr = cell.attribute;
+                if pos != Some((x, y)) {
+                    terminal.cursor_motion(pos, x, y, &mut motion);
+                    let reprinted = self.reprint_gap(painter, pos, x, y, motion.len(), |cell| {
+                        cell.attribute == last_attr && cell.link == last_link
+                    });
+                    if !reprinted {
+                        painter.command(Command::MoveCursor {
+                            from: pos,
+                            x,
+                            y,
+                            motion: &motion,
+                        })?;
+                    }
+                    pos = Some((x, y));
                 }
-                if last_x != x || last_y != y {
-                    commands.push(Command::MoveCursor { x: x, y: y });
+                let attr = self.cells[index].attribute;
+                if attr != last_attr {
+                    painter.command(Command::ChangeAttr {
+                        from: last_attr,
+                        to: attr,
+                    })?;
+                    last_attr = attr;
                 }
-                if cell.ch.display_width() == 2 && x == self.width - 1 {
-                    cell.ch = ' ';
+                if self.cells[index].link != last_link {
+                    last_link = self.cells[index].link;
+                    painter.command(match last_link {
+                        Some(id) => Command::OpenLink {
+                            id: id.get(),
+                            url: self.links.get(id),
+                        },
+                        None => Command::CloseLink,
+                    })?;
                 }
-                commands.push(Command::PutChar(cell.ch));
-                last_x = x + 1;
-                last_y = y;
-                if cell.ch.display_width() == 2 {
-                    last_x += 1;
-                    last_is_multiwidth = true;
+
+                let cell = self.cells[index];
+                let run = self.cells[index..row_end]
+                    .iter()
+                    .take_while(|&&c| c == cell)
+                    .count();
+                let erasable = is_erasable(&cell, terminal);
+                if erasable && index + run == row_end && run >= MIN_ERASE_RUN
+                    && terminal.has("el")
+                {
+                    // the cursor stays at `x`.
+                    painter.command(Command::ClearToEol)?;
+                    self.mark_painted(index, run);
+                    break;
                 }
-                self.painted_cells[index] = *cell;
+                if erasable && run >= MIN_RUN && terminal.has("ech") {
+                    // the cursor stays at `x`.
+                    painter.command(Command::EraseChars(run))?;
+                    self.mark_painted(index, run);
+                    x += run;
+                    continue;
+                }
+                // `rep` can repeat only single-byte characters.
+                if let Grapheme::Char(ch) = cell.ch {
+                    if run >= MIN_RUN && ch.is_ascii_graphic() && terminal.has("rep") {
+                        painter.command(Command::RepeatChar { ch, count: run })?;
+                        self.mark_painted(index, run);
+                        x += run;
+                        pos = if x < self.width { Some((x, y)) } else { None };
+                        continue;
+                    }
+                }
+
+                let cell = self.cells[index];
+                painter.put(cell.ch, &self.clusters);
+                let width = cell.ch.width(&self.clusters);
+                self.painted_cells[index] = cell;
+                // `relayout` keeps the continuation cell in the row.
+                if width == 2 {
+                    // the continuation cell is painted together with its wide character.
+                    self.painted_cells[index + 1] = self.cells[index + 1];
+                }
+                x += width;
+                // after writing to the last column, the cursor position depends on the terminal.
+                pos = if x < self.width { Some((x, y)) } else { None };
             }
         }
+        if last_link.is_some() {
+            painter.command(Command::CloseLink)?;
+        }
         if self.cursor.visible && !self.painted_cursor.visible {
-            commands.push(Command::ShowCursor);
+            painter.command(Command::ShowCursor)?;
         } else if !self.cursor.visible && self.painted_cursor.visible {
-            commands.push(Command::HideCursor);
+            painter.command(Command::HideCursor)?;
+        }
+        if self.cursor.shape != self.painted_cursor.shape
+            || self.cursor.blink != self.painted_cursor.blink
+        {
+            painter.command(Command::CursorShape {
+                shape: self.cursor.shape,
+                blink: self.cursor.blink,
+            })?;
+        }
+        if self.cursor.color != self.painted_cursor.color {
+            painter.command(Command::CursorColor(self.cursor.color))?;
         }
-        commands.push(Command::MoveCursor {
-            x: self.cursor.x,
-            y: self.cursor.y,
-        });
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if pos != Some((x, y)) {
+            terminal.cursor_motion(pos, x, y, &mut motion);
+            painter.command(Command::MoveCursor {
+                from: pos,
+                x,
+                y,
+                motion: &motion,
+            })?;
+        }
+        painter.flush_text()?;
         self.painted_cursor = self.cursor;
-        commands
+        self.painted_position = if x < self.width && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn mark_painted(&mut self, index: usize, len: usize) {
+        self.painted_cells[index..index + len].copy_from_slice(&self.cells[index..index + len]);
+    }
+
+    /// finds the block of rows whose content has moved vertically since the last flush, and that
+    /// saves the most repainted rows if the terminal scrolls it.
+    /// It returns `(top, bottom, shift)`, meaning that rows `top..=bottom` of the buffer are equal
+    /// to rows `top + shift..=bottom + shift` of the terminal.
+    fn find_scroll(&mut self) -> Option<(usize, usize, isize)> {
+        hash_rows(&self.cells, self.width, &mut self.new_hashes);
+        hash_rows(&self.painted_cells, self.width, &mut self.old_hashes);
+        let (new, old) = (&self.new_hashes, &self.old_hashes);
+        if new == old {
+            return None;
+        }
+
+        let height = self.height as isize;
+        let mut best = None;
+        let mut best_saved = 0;
+        for shift in (1 - height)..height {
+            if shift == 0 {
+                continue;
+            }
+            let mut top = 0;
+            let mut saved = 0;
+            for y in 0..height {
+                let src = y + shift;
+                let moved = 0 <= src && src < height && new[y as usize] == old[src as usize];
+                if moved {
+                    if new[y as usize] != old[y as usize] {
+                        saved += 1;
+                    }
+                    // scrolling exposes `shift.abs()` blank rows, which may need repainting.
+                    if saved > best_saved && saved > shift.abs() {
+                        best = Some((top as usize, y as usize, shift));
+                        best_saved = saved;
+                    }
+                } else {
+                    top = y + 1;
+                    saved = 0;
+                }
+            }
+        }
+        best
+    }
+
+    /// scrolls rows `top..=bottom` of the terminal by `shift` as found by `find_scroll`, and
+    /// updates `painted_cells` to what the terminal shows after scrolling.
+    fn scroll<F>(
+        &mut self,
+        painter: &mut Painter<F>,
+        top: usize,
+        bottom: usize,
+        shift: isize,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Command) -> io::Result<()>,
+    {
+        // the scroll region covers both of the source and the destination.
+        let (top, bottom) = if shift > 0 {
+            (top, bottom + shift as usize)
+        } else {
+            (top - (-shift) as usize, bottom)
+        };
+        painter.command(Command::Scroll {
+            top,
+            bottom,
+            shift,
+            height: self.height,
+        })?;
+        for dirty in &mut self.dirty[top..bottom + 1] {
+            *dirty = true;
+        }
+        let width = self.width;
+        let region = &mut self.painted_cells[top * width..(bottom + 1) * width];
+        let n = shift.unsigned_abs() * width;
+        if shift > 0 {
+            region.rotate_left(n);
+            let len = region.len();
+            for cell in &mut region[len - n..] {
+                *cell = Cell::default();
+            }
+        } else {
+            region.rotate_right(n);
+            for cell in &mut region[..n] {
+                *cell = Cell::default();
+            }
+        }
+        Ok(())
+    }
+
+    /// moves the cursor from `from` to `(x, y)` by re-printing the unchanged cells between them,
+    /// if it is cheaper than the cursor motion of `move_cost` bytes and every cell satisfies
+    /// `reusable`. It returns true if the cells are re-printed.
+    fn reprint_gap<F, R>(
+        &self,
+        painter: &mut Painter<F>,
+        from: Option<(usize, usize)>,
+        x: usize,
+        y: usize,
+        move_cost: usize,
+        reusable: R,
+    ) -> bool
+    where
+        F: FnMut(Command) -> io::Result<()>,
+        R: Fn(&Cell) -> bool,
+    {
+        // longer gaps are always cheaper to jump over.
+        const MAX_GAP: usize = 8;
+        let from_x = match from {
+            Some((from_x, from_y)) if from_y == y && from_x < x && x - from_x <= MAX_GAP => from_x,
+            _ => return false,
+        };
+        let start = self.index(from_x, y).unwrap();
+        let end = start + x - from_x;
+        let mut cost = 0;
+        for (cell, painted) in self.cells[start..end]
+            .iter()
+            .zip(&self.painted_cells[start..end])
+        {
+            if cell != painted || cell.ch.width(&self.clusters) != 1 || !reusable(cell) {
+                return false;
+            }
+            cost += cell.ch.len(&self.clusters);
+        }
+        if move_cost < cost {
+            return false;
+        }
+        for cell in &self.cells[start..end] {
+            painter.put(cell.ch, &self.clusters);
+        }
+        true
     }
 
     pub fn size(&self) -> (usize, usize) {
         (self.width, self.height)
     }
+
+    /// returns the cursor state that the terminal is known to have.
+    pub fn painted_cursor(&self) -> Cursor {
+        self.painted_cursor
+    }
+}
+
+/// `Painter` passes commands to `emit`, batching consecutive characters into `Command::PutStr`.
+struct Painter<F> {
+    text: String,
+    emit: F,
+}
+
+impl<F: FnMut(Command) -> io::Result<()>> Painter<F> {
+    fn put(&mut self, g: Grapheme, clusters: &StringTable) {
+        g.push_to(&mut self.text, clusters);
+    }
+
+    fn command(&mut self, command: Command) -> io::Result<()> {
+        self.flush_text()?;
+        (self.emit)(command)
+    }
+
+    fn flush_text(&mut self) -> io::Result<()> {
+        if !self.text.is_empty() {
+            (self.emit)(Command::PutStr(&self.text))?;
+            self.text.clear();
+        }
+        Ok(())
+    }
+}
+
+fn hash_rows(cells: &[Cell], width: usize, hashes: &mut Vec<u64>) {
+    hashes.clear();
+    hashes.extend(cells.chunks(width.max(1)).map(|row| {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        hasher.finish()
+    }));
+}
+
+// erasing the rest of a line (`el`) is cheaper than printing blanks for runs of this length.
+const MIN_ERASE_RUN: usize = 4;
+// erasing (`ech`) or repeating (`rep`) characters is cheaper for runs of this length, even if
+// the cursor has to be moved afterwards.
+const MIN_RUN: usize = 8;
+
+/// returns true if terminals can render `cell` by erasing it with the current attribute.
+/// Erased cells have the current background color only if the terminal has `bce`.
+fn is_erasable(cell: &Cell, terminal: &Terminal) -> bool {
+    cell.ch == Grapheme::Char(' ') && cell.link.is_none() && cell.attribute.effect.is_empty()
+        && (cell.attribute.bg == Color::Default || terminal.has_bce())
+}
+
+// OSC 8 only allows bytes in the 32-126 range in its uri, and anything else could be used to
+// inject escape sequences. Percent-encode such bytes.
+fn sanitize_url(url: &str) -> String {
+    let mut sanitized = String::with_capacity(url.len());
+    for &b in url.as_bytes() {
+        if (32..=126).contains(&b) {
+            sanitized.push(b as char);
+        } else {
+            sanitized.push_str(&format!("%{:02X}", b));
+        }
+    }
+    sanitized
+}
+
+/// flushes `screen` and returns the `Debug` representations of the emitted commands.
+#[cfg(test)]
+fn flush_commands(screen: &mut Screen, terminal: &Terminal) -> Vec<String> {
+    let mut commands = Vec::new();
+    screen
+        .flush(terminal, |c| {
+            commands.push(match c {
+                // the motion is tested with `Terminal::cursor_motion`.
+                Command::MoveCursor { from, x, y, .. } => {
+                    format!("MoveCursor {{ from: {:?}, x: {}, y: {} }}", from, x, y)
+                }
+                c => format!("{:?}", c),
+            });
+            Ok(())
+        })
+        .unwrap();
+    commands
+}
+
+#[test]
+fn test_link_spans_run_of_cells() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(10, 2);
+    screen.print_link(0, 0, "ab", "http://example.com/\x1b", Attribute::default());
+    screen.print(2, 0, "c", Attribute::default());
+    screen.cursor.x = 3;
+    assert_eq!(
+        flush_commands(&mut screen, &terminal),
+        vec![
+            "ResetAttr",
+            "MoveCursor { from: None, x: 0, y: 0 }",
+            r#"OpenLink { id: 1, url: "http://example.com/%1B" }"#,
+            r#"PutStr("ab")"#,
+            "CloseLink",
+            r#"PutStr("c")"#,
+        ]
+    );
+}
+
+#[test]
+fn test_reprint_short_gap() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(20, 2);
+    screen.print(0, 0, "abcdefghijklmnop", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    screen.print(0, 0, "A", Attribute::default());
+    screen.print(3, 0, "D", Attribute::default());
+    screen.print(15, 0, "P", Attribute::default());
+    screen.cursor.x = 16;
+    // "bc" is re-printed, while the cursor jumps over "efghijklmno".
+    assert_eq!(
+        flush_commands(&mut screen, &terminal),
+        vec![
+            "ResetAttr",
+            r#"PutStr("AbcD")"#,
+            "MoveCursor { from: Some((4, 0)), x: 15, y: 0 }",
+            r#"PutStr("P")"#,
+        ]
+    );
+}
+
+#[test]
+fn test_scroll_up() {
+    let terminal = ::terminal::dumb_xterm();
+    let lines = ["one", "two", "three", "four", "five", "six"];
+    let mut screen = Screen::new(10, 5);
+    for (y, line) in lines[..5].iter().enumerate() {
+        screen.print(0, y, line, Attribute::default());
+    }
+    flush_commands(&mut screen, &terminal);
+    screen.clear();
+    for (y, line) in lines[1..].iter().enumerate() {
+        screen.print(0, y, line, Attribute::default());
+    }
+    let commands = flush_commands(&mut screen, &terminal);
+    assert!(commands.contains(&"Scroll { top: 0, bottom: 4, shift: 1, height: 5 }".to_string()));
+    let printed: Vec<_> = commands
+        .iter()
+        .filter(|c| c.starts_with("PutStr"))
+        .collect();
+    assert_eq!(printed, vec![r#"PutStr("six")"#]);
+    assert_eq!(screen.cells, screen.painted_cells);
+}
+
+#[test]
+fn test_erase_and_repeat_runs() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(20, 2);
+    screen.print(0, 0, "abcdefghijklmnopqrst", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    screen.clear();
+    screen.print(0, 0, "ab", Attribute::default());
+    screen.print(0, 1, "==========", Attribute::default());
+    let commands = flush_commands(&mut screen, &terminal);
+    assert!(commands.contains(&"ClearToEol".to_string()));
+    assert!(commands.contains(&"RepeatChar { ch: '=', count: 10 }".to_string()));
+    assert!(!commands
+        .iter()
+        .any(|c| c.starts_with("PutStr") && c.contains(' ')));
+    assert_eq!(screen.cells, screen.painted_cells);
+}
+
+#[test]
+fn test_move_after_erase() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(30, 2);
+    screen.print(0, 0, &"a".repeat(30), Attribute::default());
+    screen.print(0, 1, &"a".repeat(30), Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    screen.print(10, 0, &" ".repeat(10), Attribute::default());
+    screen.print(25, 0, "b", Attribute::default());
+    screen.print(2, 1, &" ".repeat(28), Attribute::default());
+    screen.cursor.x = 5;
+    // moves after erasing start from where the erase started.
+    assert_eq!(
+        flush_commands(&mut screen, &terminal)[1..],
+        [
+            "MoveCursor { from: Some((0, 0)), x: 10, y: 0 }",
+            "EraseChars(10)",
+            "MoveCursor { from: Some((10, 0)), x: 25, y: 0 }",
+            r#"PutStr("b")"#,
+            "MoveCursor { from: Some((26, 0)), x: 2, y: 1 }",
+            "ClearToEol",
+            "MoveCursor { from: Some((2, 1)), x: 5, y: 0 }",
+        ]
+    );
+}
+
+#[test]
+fn test_flush_skips_clean_rows() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(10, 3);
+    screen.print(0, 1, "hello", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    assert_eq!(screen.dirty, vec![false; 3]);
+
+    // redrawing the same content touches every row, but nothing is painted.
+    screen.clear();
+    screen.print(0, 1, "hello", Attribute::default());
+    assert_eq!(flush_commands(&mut screen, &terminal), vec!["ResetAttr"]);
+
+    screen.put_cell(0, 2, Cell::new('x'));
+    assert_eq!(screen.dirty, vec![false, false, true]);
+}
+
+#[test]
+fn test_invalidate_repaints_everything() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(5, 1);
+    screen.print(0, 0, "ab", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    screen.invalidate();
+    assert_eq!(
+        flush_commands(&mut screen, &terminal),
+        vec![
+            "ResetAttr",
+            "Clear",
+            "MoveCursor { from: None, x: 0, y: 0 }",
+            r#"PutStr("ab")"#,
+            "MoveCursor { from: Some((2, 0)), x: 0, y: 0 }",
+        ]
+    );
+}
+
+#[test]
+fn test_print_grapheme_clusters() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(10, 1);
+    // "e" with an acute accent, a thumbs up with a skin tone, and a flag.
+    screen.print(0, 0, "e\u{301}\u{1f44d}\u{1f3fd}\u{1f1ef}\u{1f1f5}!", Attribute::default());
+    assert_eq!(screen.grapheme(0, 0).unwrap(), "e\u{301}");
+    assert_eq!(screen.grapheme(1, 0).unwrap(), "\u{1f44d}\u{1f3fd}");
+    assert_eq!(screen.grapheme(3, 0).unwrap(), "\u{1f1ef}\u{1f1f5}");
+    assert_eq!(screen.cells[5], Cell::new('!'));
+    assert_eq!(
+        flush_commands(&mut screen, &terminal)[2],
+        format!("PutStr({:?})", "e\u{301}\u{1f44d}\u{1f3fd}\u{1f1ef}\u{1f1f5}!")
+    );
+
+    // clusters of any length are stored as they are.
+    let zalgo = format!("a{}", "\u{301}".repeat(100));
+    let kiss = concat!(
+        "\u{1f469}\u{1f3fb}\u{200d}\u{2764}\u{fe0f}\u{200d}\u{1f48b}",
+        "\u{200d}\u{1f468}\u{1f3fc}"
+    );
+    screen.clear();
+    screen.print(0, 0, &zalgo, Attribute::default());
+    screen.print(1, 0, kiss, Attribute::default());
+    assert_eq!(screen.grapheme(0, 0).unwrap(), zalgo);
+    assert_eq!(screen.grapheme(1, 0).unwrap(), kiss);
+    assert_eq!(screen.get_cell(1, 0).unwrap().ch(), '\u{1f469}');
+}
+
+#[test]
+fn test_overwrite_wide_char() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(4, 1);
+    screen.print(0, 0, "あい", Attribute::default());
+    assert_eq!(screen.cells[1].ch, Grapheme::Continuation);
+    flush_commands(&mut screen, &terminal);
+
+    // overwriting the right half blanks the left half.
+    screen.put_cell(1, 0, Cell::new('x'));
+    assert_eq!(screen.cells[0], Cell::new(' '));
+    // overwriting the left half blanks the right half.
+    screen.put_cell(2, 0, Cell::new('y'));
+    assert_eq!(screen.cells[3], Cell::new(' '));
+    assert_eq!(
+        flush_commands(&mut screen, &terminal)[..2],
+        ["ResetAttr", "PutStr(\" xy \")"]
+    );
+
+    // a wide character does not fit in the last column.
+    screen.put_cell(3, 0, Cell::new('う'));
+    assert_eq!(screen.cells[3], Cell::new(' '));
+    // a wide character over the left half of another one.
+    screen.print(0, 0, "えお", Attribute::default());
+    screen.put_cell(1, 0, Cell::new('か'));
+    assert_eq!(screen.cells[0], Cell::new(' '));
+    assert_eq!(screen.cells[1], Cell::new('か'));
+    assert_eq!(screen.cells[3], Cell::new(' '));
+
+    // zero-width characters are not put in their own cells.
+    screen.put_cell(0, 0, Cell::new('\u{301}'));
+    screen.put_cell(2, 0, Cell::new('\u{200b}'));
+    assert_eq!(screen.cells[0], Cell::new(' '));
+    assert_eq!(screen.cells[2].ch, Grapheme::Continuation);
+    assert_eq!(screen.text(screen.bounds()), " か");
+}
+
+#[test]
+fn test_change_width_policy() {
+    use width::{set_width_policy, WidthPolicy};
+
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(4, 2);
+    screen.print(0, 0, "abc\u{25cb}", Attribute::default());
+    screen.print(0, 1, "\u{25cb}\u{25cb}b", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    // the policy is shared with other tests, which don't print '\u{25cb}'.
+    set_width_policy(WidthPolicy {
+        overrides: vec![('\u{25cb}', '\u{25cb}', 2)],
+        ..WidthPolicy::default()
+    });
+    let commands = flush_commands(&mut screen, &terminal);
+    set_width_policy(WidthPolicy::default());
+    // the characters that have become wide have no room for their right halves.
+    assert_eq!(screen.text(screen.bounds()), "abc\n  b");
+    assert_eq!(commands[1], "Clear");
+    assert_eq!(screen.cells, screen.painted_cells);
+}
+
+#[test]
+fn test_print_controls() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(20, 1);
+    screen.print(0, 0, "b\x1b[2J\r\n\u{9b}\x7f", Attribute::default());
+    assert_eq!(
+        flush_commands(&mut screen, &terminal)[2],
+        "PutStr(\"b\u{241b}[2J\u{240d}\u{240a}\u{fffd}\u{2421}\")"
+    );
+
+    screen.print(0, 0, "a\tb", Attribute::default());
+    assert_eq!(screen.cells[1], Cell::new(' '));
+    assert_eq!(screen.cells[8], Cell::new('b'));
+    screen.set_tab_width(4);
+    screen.print(0, 0, "ab\tc", Attribute::default());
+    assert_eq!(screen.cells[4], Cell::new('c'));
+    screen.set_tab_width(0);
+    screen.print(0, 0, "\t", Attribute::default());
+    assert_eq!(screen.cells[0], Cell::new('\u{2409}'));
+}
+
+#[test]
+fn test_read_back_cells() {
+    let mut screen = Screen::new(4, 2);
+    let attr = Attribute {
+        fg: Color::Red,
+        ..Attribute::default()
+    };
+    screen.print(0, 1, "aあe\u{301}", attr);
+    let cell = screen.get_cell(1, 1).unwrap();
+    assert_eq!((cell.ch(), cell.attr()), ('あ', attr));
+    assert!(screen.get_cell(2, 1).unwrap().is_continuation());
+    assert_eq!(screen.grapheme(2, 1).unwrap(), "");
+    assert_eq!(screen.get_cell(3, 1).unwrap().ch(), 'e');
+    assert_eq!(screen.grapheme(3, 1).unwrap(), "e\u{301}");
+    assert_eq!(screen.get_cell(4, 1), None);
+    assert_eq!(screen.grapheme(4, 1), None);
+
+    assert_eq!(screen.row(0).unwrap(), &[Cell::default(); 4][..]);
+    assert_eq!(screen.row(2), None);
+    let chars: Vec<String> = screen
+        .rows()
+        .map(|row| row.iter().map(|cell| cell.ch()).collect())
+        .collect();
+    assert_eq!(chars, ["    ", "aあ e"]);
+}
+
+#[test]
+fn test_text() {
+    let mut screen = Screen::new(6, 3);
+    screen.print(0, 0, "ab  c", Attribute::default());
+    screen.print(1, 1, "あい", Attribute::default());
+    assert_eq!(screen.text(Rect::new(0, 0, 6, 3)), "ab  c\n あい\n");
+    assert_eq!(screen.text(Rect::new(1, 0, 2, 2)), "b\nあ");
+    assert_eq!(screen.text(Rect::new(3, 1, 10, 10)), "い\n");
+    assert_eq!(screen.text(Rect::new(10, 0, 2, 2)), "");
+}
+
+#[test]
+fn test_draw_box() {
+    let mut screen = Screen::new(8, 4);
+    let bounds = screen.bounds();
+    let attr = Attribute::default();
+    screen.draw_box_in(bounds, Rect::new(0, 0, 5, 4), BorderStyle::Single, Some("ab"), attr);
+    screen.draw_box_in(bounds, Rect::new(4, 0, 4, 4), BorderStyle::Single, None, attr);
+    screen.draw_line_in(bounds, (0, 2), 5, Direction::Horizontal, BorderStyle::Single, attr);
+    screen.draw_line_in(bounds, (2, 2), 2, Direction::Vertical, BorderStyle::Double, attr);
+    assert_eq!(screen.text(bounds), "┌ab─┬──┐\n│   │  │\n├─╥─┤  │\n└─╨─┴──┘");
+
+    // a line of one cell keeps its direction.
+    let mut screen = Screen::new(3, 3);
+    let bounds = screen.bounds();
+    screen.draw_line_in(bounds, (1, 1), 1, Direction::Vertical, BorderStyle::Single, attr);
+    screen.draw_box_in(bounds, Rect::new(0, 0, 1, 1), BorderStyle::Single, None, attr);
+    assert_eq!(screen.text(bounds), "─\n │\n");
+}
+
+#[test]
+fn test_print_wrapped_and_truncated() {
+    let mut screen = Screen::new(12, 7);
+    let bounds = screen.bounds();
+    let attr = Attribute::default();
+    let rect = Rect::new(1, 0, 5, 2);
+    assert_eq!(screen.print_wrapped_in(bounds, rect, "ab cd efg hi", attr), 2);
+    screen.set_tab_width(4);
+    let rect = Rect::new(0, 2, 6, 2);
+    assert_eq!(screen.print_wrapped_in(bounds, rect, "a\tbc d", attr), 2);
+
+    let s = "/home/user/src/lib.rs";
+    let row = |y| Rect::new(0, y, 12, 1);
+    let (start, middle, end) = (Ellipsis::Start, Ellipsis::Middle, Ellipsis::End);
+    assert_eq!(screen.print_truncated_in(bounds, row(4), s, start, Align::Left, attr), 12);
+    assert_eq!(screen.print_truncated_in(bounds, row(5), s, middle, Align::Left, attr), 12);
+    assert_eq!(screen.print_truncated_in(bounds, row(6), "a\tb", end, Align::Right, attr), 5);
+    assert_eq!(
+        screen.text(bounds),
+        " ab cd\n efg\na   bc\nd\n…/src/lib.rs\n/home/…ib.rs\n       a   b"
+    );
+
+    screen.clear();
+    let rect = Rect::new(0, 0, 6, 1);
+    assert_eq!(screen.print_truncated_in(bounds, rect, s, end, Align::Left, attr), 6);
+    assert_eq!(screen.print_truncated_in(bounds, row(1), "ab", end, Align::Center, attr), 2);
+    assert_eq!(screen.text(Rect::new(0, 0, 12, 2)), "/home…\n     ab");
+}
+
+#[test]
+fn test_release_strings() {
+    let terminal = ::terminal::dumb_xterm();
+    let mut screen = Screen::new(10, 2);
+    for i in 0..5 {
+        screen.clear();
+        screen.print_link(0, 0, "a", &format!("http://example.com/{}", i), Attribute::default());
+        screen.print_link(0, 5, "b", "http://example.com/clipped", Attribute::default());
+        screen.print(1, 0, &format!("{}\u{301}", i), Attribute::default());
+        flush_commands(&mut screen, &terminal);
+        assert_eq!(screen.links.strings.iter().flatten().count(), 1);
+        assert_eq!(screen.clusters.strings.iter().flatten().count(), 1);
+    }
+    // the ids of released strings are reused.
+    assert_eq!((screen.links.strings.len(), screen.clusters.strings.len()), (1, 1));
+    assert_eq!(screen.get_cell(0, 0).unwrap().link, LinkId::new(1));
+
+    // a cell referring to a released cluster is put as its first character.
+    let cell = screen.get_cell(1, 0).unwrap();
+    screen.clear();
+    screen.print(5, 0, "x\u{301}", Attribute::default());
+    flush_commands(&mut screen, &terminal);
+    screen.put_cell(1, 0, cell);
+    assert_eq!(screen.grapheme(1, 0).unwrap(), "4");
 }
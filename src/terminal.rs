@@ -1,20 +1,127 @@
+use std::collections::HashMap;
+use std::env;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use term::terminfo::TermInfo;
+use term::terminfo::parm::{self, Param, Variables};
 use libc;
 
 use key::Key;
-use attr::{Color, Effect};
+use attr::{Attribute, Color, Effect};
+use screen::CursorShape;
+use event::ColorSlot;
+use border::{self, BorderStyle};
+
+/// DEC private mode for synchronized output.
+/// See https://gist.github.com/christianparpart/d8a62cc1ab659194337d73e399004036
+pub const SYNCHRONIZED_OUTPUT_MODE: u16 = 2026;
+
+/// `SynchronizedOutput` configures how termfest asks the terminal to paint a flush at once.
+/// Without it, large redraws can be painted while they are streamed, and look torn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynchronizedOutput {
+    /// use DEC private mode 2026 if the terminal reports that it supports the mode.
+    Auto,
+    /// always use DEC private mode 2026.
+    Mode2026,
+    /// always use the older DCS sequences (`DCS = 1 s` and `DCS = 2 s`).
+    Dcs,
+    Disabled,
+}
+
+/// characters of the VT100 alternate character set, and their keys in the `acsc` capability.
+static ACS_CHARS: &[(char, u8)] = &[
+    ('◆', b'`'),
+    ('▒', b'a'),
+    ('°', b'f'),
+    ('±', b'g'),
+    ('┘', b'j'),
+    ('┐', b'k'),
+    ('┌', b'l'),
+    ('└', b'm'),
+    ('┼', b'n'),
+    ('⎺', b'o'),
+    ('⎻', b'p'),
+    ('─', b'q'),
+    ('⎼', b'r'),
+    ('⎽', b's'),
+    ('├', b't'),
+    ('┤', b'u'),
+    ('┴', b'v'),
+    ('┬', b'w'),
+    ('│', b'x'),
+    ('≤', b'y'),
+    ('≥', b'z'),
+    ('π', b'{'),
+    ('≠', b'|'),
+    ('£', b'}'),
+    ('·', b'~'),
+];
 
 #[derive(Debug)]
 pub struct Terminal {
     terminfo: TermInfo,
+    /// set by the ttyin reader when the terminal replies to the query of the mode 2026.
+    synchronized_output_supported: AtomicBool,
+    /// the number of color queries that the terminal has not replied to yet.
+    /// Terminals which don't support them never reply, so it may stay positive.
+    color_queries: AtomicUsize,
+    /// false if the locale is not UTF-8. Then line-drawing characters are written with the
+    /// alternate character set, or as ASCII characters.
+    utf8: bool,
+    /// characters which the terminal displays in the alternate character set, and the bytes
+    /// to write them.
+    acs: HashMap<char, u8>,
 }
 
 impl Terminal {
     pub fn from_env() -> io::Result<Self> {
         let terminfo = TermInfo::from_env()?;
-        Ok(Terminal { terminfo: terminfo })
+        let mut terminal = Terminal::new(terminfo);
+        terminal.utf8 = locale_is_utf8();
+        Ok(terminal)
+    }
+
+    fn new(terminfo: TermInfo) -> Self {
+        let mut acs = HashMap::new();
+        let strings = &terminfo.strings;
+        if strings.contains_key("smacs") && strings.contains_key("rmacs") {
+            if let Some(acsc) = strings.get("acsc") {
+                for pair in acsc.chunks(2).filter(|pair| pair.len() == 2) {
+                    if let Some(&(ch, _)) = ACS_CHARS.iter().find(|&&(_, key)| key == pair[0]) {
+                        acs.insert(ch, pair[1]);
+                    }
+                }
+            }
+        }
+        Terminal {
+            terminfo,
+            synchronized_output_supported: AtomicBool::new(false),
+            color_queries: AtomicUsize::new(0),
+            utf8: true,
+            acs,
+        }
+    }
+
+    /// records the state of a DEC private mode reported by the terminal.
+    pub fn set_mode_status(&self, mode: u16, status: u8) {
+        if mode == SYNCHRONIZED_OUTPUT_MODE {
+            let supported = status == 1 || status == 2;
+            self.synchronized_output_supported
+                .store(supported, Ordering::Relaxed);
+        }
+    }
+
+    /// returns true if a reply to a color query may arrive.
+    pub fn color_query_pending(&self) -> bool {
+        self.color_queries.load(Ordering::Relaxed) > 0
+    }
+
+    /// records that the terminal has replied to a color query.
+    pub fn color_replied(&self) {
+        let _ = self.color_queries
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
     }
 
     fn write_if_exists<W: Write>(&self, mut w: W, typ: &str) -> io::Result<()> {
@@ -25,6 +132,34 @@ impl Terminal {
         }
     }
 
+    /// expands the parameterized capability `typ` with `params` and writes it.
+    /// It returns `false` if the terminal doesn't have the capability.
+    fn write_with_params<W: Write>(
+        &self,
+        mut w: W,
+        typ: &str,
+        params: &[Param],
+    ) -> io::Result<bool> {
+        let cap = match self.terminfo.strings.get(typ) {
+            Some(cap) => cap,
+            None => return Ok(false),
+        };
+        let bytes = parm::expand(cap, params, &mut Variables::new())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+        w.write_all(&bytes)?;
+        Ok(true)
+    }
+
+    /// returns true if the terminal has the string capability `typ`.
+    pub fn has(&self, typ: &str) -> bool {
+        self.terminfo.strings.contains_key(typ)
+    }
+
+    /// returns true if erasing fills cells with the current background color.
+    pub fn has_bce(&self) -> bool {
+        self.terminfo.bools.get("bce").cloned().unwrap_or(false)
+    }
+
     pub fn enter_ca<W: Write>(&self, w: W) -> io::Result<()> {
         self.write_if_exists(w, "smcup")
     }
@@ -45,9 +180,126 @@ impl Terminal {
         self.write_if_exists(w, "clear")
     }
 
-    pub fn move_cursor<W: Write>(&self, mut w: W, x: usize, y: usize) -> io::Result<()> {
-        w.write(&[0x1b])?;
-        write!(w, "[{};{}H", y + 1, x + 1)
+    /// saves the window title and icon name on the terminal's title stack (XTWINOPS 22).
+    pub fn push_title<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all("\u{1b}[22;0t".as_bytes())
+    }
+
+    /// restores the window title and icon name saved by `push_title` (XTWINOPS 23).
+    pub fn pop_title<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all("\u{1b}[23;0t".as_bytes())
+    }
+
+    /// sets the window title, with `tsl`/`fsl` if the terminal has them, or with OSC 2.
+    pub fn set_title<W: Write>(&self, mut w: W, title: &str) -> io::Result<()> {
+        let title = strip_controls(title);
+        if self.terminfo.strings.contains_key("fsl")
+            && self.write_with_params(&mut w, "tsl", &[Param::Number(0)])?
+        {
+            w.write_all(title.as_bytes())?;
+            return self.write_if_exists(w, "fsl");
+        }
+        write!(w, "\u{1b}]2;{}\u{7}", title)
+    }
+
+    /// sets the icon name (usually shown in a taskbar or a tab) with OSC 1.
+    pub fn set_icon_name<W: Write>(&self, mut w: W, name: &str) -> io::Result<()> {
+        write!(w, "\u{1b}]1;{}\u{7}", strip_controls(name))
+    }
+
+    fn expand(&self, typ: &str, params: &[usize]) -> Option<Vec<u8>> {
+        let cap = self.terminfo.strings.get(typ)?;
+        let params: Vec<_> = params.iter().map(|&p| Param::Number(p as i32)).collect();
+        parm::expand(cap, &params, &mut Variables::new()).ok()
+    }
+
+    // `cap` repeated `n` times.
+    fn repeat(&self, typ: &str, n: usize) -> Option<Vec<u8>> {
+        // repeating more than this is never cheaper than parameterized capabilities.
+        const MAX_REPEAT: usize = 8;
+        if n > MAX_REPEAT {
+            return None;
+        }
+        self.terminfo.strings.get(typ).map(|cap| cap.repeat(n))
+    }
+
+    fn absolute_motion(&self, x: usize, y: usize) -> Vec<u8> {
+        self.expand("cup", &[y, x])
+            .unwrap_or_else(|| format!("\u{1b}[{};{}H", y + 1, x + 1).into_bytes())
+    }
+
+    fn vertical_motion(&self, from: usize, to: usize) -> Option<Vec<u8>> {
+        if from == to {
+            return Some(Vec::new());
+        }
+        let (repeated, parameterized) = if from < to {
+            let n = to - from;
+            (self.repeat("cud1", n), self.expand("cud", &[n]))
+        } else {
+            let n = from - to;
+            (self.repeat("cuu1", n), self.expand("cuu", &[n]))
+        };
+        shortest(
+            repeated
+                .into_iter()
+                .chain(parameterized)
+                .chain(self.expand("vpa", &[to])),
+        )
+    }
+
+    fn horizontal_motion(&self, from: usize, to: usize) -> Option<Vec<u8>> {
+        if from == to {
+            return Some(Vec::new());
+        }
+        let (repeated, parameterized) = if from < to {
+            let n = to - from;
+            (self.repeat("cuf1", n), self.expand("cuf", &[n]))
+        } else {
+            let n = from - to;
+            (self.repeat("cub1", n), self.expand("cub", &[n]))
+        };
+        // carriage return, followed by moving forward.
+        let from_cr = if from == 0 {
+            None
+        } else {
+            self.terminfo.strings.get("cr").and_then(|cr| {
+                let mut motion = cr.clone();
+                motion.extend(self.horizontal_motion(0, to)?);
+                Some(motion)
+            })
+        };
+        shortest(
+            repeated
+                .into_iter()
+                .chain(parameterized)
+                .chain(self.expand("hpa", &[to]))
+                .chain(from_cr),
+        )
+    }
+
+    /// returns the shortest byte sequence that moves the cursor from `from` to `(x, y)`,
+    /// choosing among absolute and relative movements like ncurses' mvcur.
+    /// `from` is `None` if the cursor position is unknown.
+    /// The sequence is written to `motion`, so that its length can be compared with other ways
+    /// before passing it to `Command::MoveCursor`.
+    pub fn cursor_motion(
+        &self,
+        from: Option<(usize, usize)>,
+        x: usize,
+        y: usize,
+        motion: &mut Vec<u8>,
+    ) {
+        let absolute = self.absolute_motion(x, y);
+        let relative = from.and_then(|(from_x, from_y)| {
+            let mut motion = self.vertical_motion(from_y, y)?;
+            motion.extend(self.horizontal_motion(from_x, x)?);
+            Some(motion)
+        });
+        motion.clear();
+        match relative {
+            Some(ref relative) if relative.len() < absolute.len() => motion.extend(relative),
+            _ => motion.extend(absolute),
+        }
     }
 
     pub fn hide_cursor<W: Write>(&self, w: W) -> io::Result<()> {
@@ -58,76 +310,251 @@ impl Terminal {
         self.write_if_exists(w, "cnorm")
     }
 
-    pub fn put_cell<W: Write>(&self, mut w: W, ch: char) -> io::Result<()> {
-        let mut buf = [0; 4];
-        w.write_all(ch.encode_utf8(&mut buf).as_bytes())
+    /// sets the cursor shape with DECSCUSR.
+    pub fn cursor_shape<W: Write>(
+        &self,
+        mut w: W,
+        shape: CursorShape,
+        blink: bool,
+    ) -> io::Result<()> {
+        let ps = match shape {
+            CursorShape::Default => 0,
+            CursorShape::Block => 2,
+            CursorShape::Underline => 4,
+            CursorShape::Bar => 6,
+        };
+        let ps = if blink && ps != 0 { ps - 1 } else { ps };
+        write!(w, "\u{1b}[{} q", ps)
     }
 
-    pub fn reset_attr<W: Write>(&self, w: W) -> io::Result<()> {
-        self.write_if_exists(w, "sgr0")
+    /// sets the cursor color with OSC 12, or resets it with OSC 112 for `Color::Default`.
+    pub fn cursor_color<W: Write>(&self, mut w: W, color: Color) -> io::Result<()> {
+        match rgb(color) {
+            Some((r, g, b)) => write!(w, "\u{1b}]12;rgb:{:02x}/{:02x}/{:02x}\u{7}", r, g, b),
+            None => w.write_all("\u{1b}]112\u{7}".as_bytes()),
+        }
     }
 
-    pub fn fg<W: Write>(&self, mut w: W, color: Color) -> io::Result<()> {
-        match color {
-            Color::Default => w.write_all("\u{1b}[39m".as_bytes()),
-            Color::Black => w.write_all("\u{1b}[30m".as_bytes()),
-            Color::Red => w.write_all("\u{1b}[31m".as_bytes()),
-            Color::Green => w.write_all("\u{1b}[32m".as_bytes()),
-            Color::Yellow => w.write_all("\u{1b}[33m".as_bytes()),
-            Color::Blue => w.write_all("\u{1b}[34m".as_bytes()),
-            Color::Magenta => w.write_all("\u{1b}[35m".as_bytes()),
-            Color::Cyan => w.write_all("\u{1b}[36m".as_bytes()),
-            Color::White => w.write_all("\u{1b}[37m".as_bytes()),
-            Color::EightBit(x) => write!(w, "\u{1b}[38;5;{}m", x),
-            Color::Rgb(r, g, b) => write!(w, "\u{1b}[38;2;{};{};{}m", r, g, b),
+    /// asks the terminal for the color of `slot` with OSC 10, 11 or 4.
+    pub fn query_color<W: Write>(&self, mut w: W, slot: ColorSlot) -> io::Result<()> {
+        self.color_queries.fetch_add(1, Ordering::Relaxed);
+        match slot {
+            ColorSlot::Foreground => w.write_all("\u{1b}]10;?\u{7}".as_bytes()),
+            ColorSlot::Background => w.write_all("\u{1b}]11;?\u{7}".as_bytes()),
+            ColorSlot::Palette(index) => write!(w, "\u{1b}]4;{};?\u{7}", index),
         }
     }
 
-    pub fn bg<W: Write>(&self, mut w: W, color: Color) -> io::Result<()> {
-        match color {
-            Color::Default => w.write_all("\u{1b}[49m".as_bytes()),
-            Color::Black => w.write_all("\u{1b}[40m".as_bytes()),
-            Color::Red => w.write_all("\u{1b}[41m".as_bytes()),
-            Color::Green => w.write_all("\u{1b}[42m".as_bytes()),
-            Color::Yellow => w.write_all("\u{1b}[43m".as_bytes()),
-            Color::Blue => w.write_all("\u{1b}[44m".as_bytes()),
-            Color::Magenta => w.write_all("\u{1b}[45m".as_bytes()),
-            Color::Cyan => w.write_all("\u{1b}[46m".as_bytes()),
-            Color::White => w.write_all("\u{1b}[47m".as_bytes()),
-            Color::EightBit(x) => write!(w, "\u{1b}[48;5;{}m", x),
-            Color::Rgb(r, g, b) => write!(w, "\u{1b}[48;2;{};{};{}m", r, g, b),
+    /// asks the terminal for the state of the DEC private mode `mode` (DECRQM).
+    pub fn query_mode<W: Write>(&self, mut w: W, mode: u16) -> io::Result<()> {
+        write!(w, "\u{1b}[?{}$p", mode)
+    }
+
+    /// starts a synchronized update configured by `mode`.
+    /// It returns the kind of the started update (`Mode2026` or `Dcs`) to be passed to
+    /// `end_synchronized_update`, or `None` if no update is started.
+    pub fn begin_synchronized_update<W: Write>(
+        &self,
+        mut w: W,
+        mode: SynchronizedOutput,
+    ) -> io::Result<Option<SynchronizedOutput>> {
+        let mode = match mode {
+            SynchronizedOutput::Auto => {
+                if self.synchronized_output_supported.load(Ordering::Relaxed) {
+                    SynchronizedOutput::Mode2026
+                } else {
+                    return Ok(None);
+                }
+            }
+            SynchronizedOutput::Disabled => return Ok(None),
+            mode => mode,
+        };
+        match mode {
+            SynchronizedOutput::Dcs => w.write_all("\u{1b}P=1s\u{1b}\\".as_bytes())?,
+            _ => write!(w, "\u{1b}[?{}h", SYNCHRONIZED_OUTPUT_MODE)?,
         }
+        Ok(Some(mode))
     }
 
-    pub fn effect<W: Write>(&self, mut w: W, effect: Effect) -> io::Result<()> {
-        if effect.contains(Effect::BOLD) {
-            self.write_if_exists(&mut w, "bold")?;
+    pub fn end_synchronized_update<W: Write>(
+        &self,
+        mut w: W,
+        mode: SynchronizedOutput,
+    ) -> io::Result<()> {
+        match mode {
+            SynchronizedOutput::Dcs => w.write_all("\u{1b}P=2s\u{1b}\\".as_bytes()),
+            _ => write!(w, "\u{1b}[?{}l", SYNCHRONIZED_OUTPUT_MODE),
         }
-        if effect.contains(Effect::DIM) {
-            self.write_if_exists(&mut w, "dim")?;
+    }
+
+    /// erases from the cursor to the end of the line, without moving the cursor.
+    pub fn clear_to_eol<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_if_exists(w, "el")
+    }
+
+    /// erases `n` characters from the cursor, without moving the cursor.
+    pub fn erase_chars<W: Write>(&self, w: W, n: usize) -> io::Result<()> {
+        self.write_with_params(w, "ech", &[Param::Number(n as i32)])
+            .map(|_| ())
+    }
+
+    /// prints `ch` `count` times. `ch` must be an ascii character.
+    pub fn repeat_char<W: Write>(&self, w: W, ch: char, count: usize) -> io::Result<()> {
+        debug_assert!(ch.is_ascii());
+        self.write_with_params(
+            w,
+            "rep",
+            &[Param::Number(ch as i32), Param::Number(count as i32)],
+        ).map(|_| ())
+    }
+
+    pub fn put_str<W: Write>(&self, mut w: W, s: &str) -> io::Result<()> {
+        if self.utf8 {
+            return w.write_all(s.as_bytes());
         }
-        if effect.contains(Effect::UNDERLINE) {
-            self.write_if_exists(&mut w, "smul")?;
+        // line-drawing characters are likely to be garbled without UTF-8.
+        let mut alternate = false;
+        let mut buf = [0; 4];
+        for ch in s.chars() {
+            let single = border::restyle(ch, BorderStyle::Single).unwrap_or(ch);
+            if let Some(&byte) = self.acs.get(&single) {
+                if !alternate {
+                    self.write_if_exists(&mut w, "smacs")?;
+                    alternate = true;
+                }
+                w.write_all(&[byte])?;
+                continue;
+            }
+            if alternate {
+                self.write_if_exists(&mut w, "rmacs")?;
+                alternate = false;
+            }
+            let ch = border::restyle(ch, BorderStyle::Ascii).unwrap_or(ch);
+            w.write_all(ch.encode_utf8(&mut buf).as_bytes())?;
         }
-        if effect.contains(Effect::BLINK) {
-            self.write_if_exists(&mut w, "blink")?;
+        if alternate {
+            self.write_if_exists(&mut w, "rmacs")?;
         }
-        if effect.contains(Effect::REVERSE) {
-            self.write_if_exists(&mut w, "rev")?;
+        Ok(())
+    }
+
+    pub fn reset_attr<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_if_exists(w, "sgr0")
+    }
+
+    /// returns true if the terminal can scroll a part of the screen, with a scroll region or
+    /// with inserting and deleting lines.
+    pub fn can_scroll(&self) -> bool {
+        let has = |typ| self.has(typ);
+        (has("csr") && has("ind") && has("ri")) || (has("il1") && has("dl1"))
+    }
+
+    // writes the parameterized capability `typ` if it exists, or `step` repeated `n` times.
+    fn write_n_times<W: Write>(&self, mut w: W, typ: &str, step: &str, n: usize) -> io::Result<()> {
+        if self.write_with_params(&mut w, typ, &[Param::Number(n as i32)])? {
+            return Ok(());
+        }
+        for _ in 0..n {
+            self.write_if_exists(&mut w, step)?;
         }
         Ok(())
     }
 
-    pub fn write<W: Write>(&self, w: W, command: Command) -> io::Result<()> {
+    /// scrolls the rows `top..=bottom` by `shift` rows, up if `shift` is positive and down
+    /// otherwise. Rows scrolled in are blank.
+    /// The cursor position is undefined after scrolling.
+    pub fn scroll<W: Write>(
+        &self,
+        mut w: W,
+        top: usize,
+        bottom: usize,
+        shift: isize,
+        height: usize,
+    ) -> io::Result<()> {
+        let n = shift.unsigned_abs();
+        let has = |typ| self.has(typ);
+        if has("csr") && has("ind") && has("ri") {
+            self.write_with_params(
+                &mut w,
+                "csr",
+                &[Param::Number(top as i32), Param::Number(bottom as i32)],
+            )?;
+            if shift > 0 {
+                w.write_all(&self.absolute_motion(0, bottom))?;
+                self.write_n_times(&mut w, "indn", "ind", n)?;
+            } else {
+                w.write_all(&self.absolute_motion(0, top))?;
+                self.write_n_times(&mut w, "rin", "ri", n)?;
+            }
+            self.write_with_params(
+                &mut w,
+                "csr",
+                &[Param::Number(0), Param::Number(height as i32 - 1)],
+            )?;
+            return Ok(());
+        }
+        // deleting lines pulls up all rows below, and inserting lines pushes them back.
+        let (delete_at, insert_at) = if shift > 0 {
+            (top, bottom + 1 - n)
+        } else {
+            (bottom + 1 - n, top)
+        };
+        w.write_all(&self.absolute_motion(0, delete_at))?;
+        self.write_n_times(&mut w, "dl", "dl1", n)?;
+        if shift < 0 || bottom + 1 < height {
+            w.write_all(&self.absolute_motion(0, insert_at))?;
+            self.write_n_times(&mut w, "il", "il1", n)?;
+        }
+        Ok(())
+    }
+
+    /// changes the current attribute from `from` to `to` with a single SGR sequence.
+    /// Only the differences are written, unless resetting everything is shorter.
+    pub fn change_attr<W: Write>(
+        &self,
+        mut w: W,
+        from: Attribute,
+        to: Attribute,
+    ) -> io::Result<()> {
+        let params = sgr_params(from, to);
+        if params.is_empty() {
+            return Ok(());
+        }
+        write!(w, "\u{1b}[{}m", params)
+    }
+
+    /// starts an OSC 8 hyperlink. `id` lets the terminal join cells of the same link even if they
+    /// are written separately.
+    pub fn open_link<W: Write>(&self, mut w: W, id: u32, url: &str) -> io::Result<()> {
+        write!(w, "\u{1b}]8;id={};{}\u{1b}\\", id, url)
+    }
+
+    pub fn close_link<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all("\u{1b}]8;;\u{1b}\\".as_bytes())
+    }
+
+    pub fn write<W: Write>(&self, mut w: W, command: Command) -> io::Result<()> {
         match command {
+            Command::Clear => self.clear(w),
             Command::HideCursor => self.hide_cursor(w),
             Command::ShowCursor => self.show_cursor(w),
-            Command::MoveCursor { x, y } => self.move_cursor(w, x, y),
-            Command::PutChar(ch) => self.put_cell(w, ch),
+            Command::MoveCursor { motion, .. } => w.write_all(motion),
+            Command::PutStr(s) => self.put_str(w, s),
+            Command::RepeatChar { ch, count } => self.repeat_char(w, ch, count),
+            Command::ClearToEol => self.clear_to_eol(w),
+            Command::EraseChars(n) => self.erase_chars(w, n),
             Command::ResetAttr => self.reset_attr(w),
-            Command::Fg(c) => self.fg(w, c),
-            Command::Bg(c) => self.bg(w, c),
-            Command::Effect(a) => self.effect(w, a),
+            Command::ChangeAttr { from, to } => self.change_attr(w, from, to),
+            Command::OpenLink { id, url } => self.open_link(w, id, url),
+            Command::CloseLink => self.close_link(w),
+            Command::Scroll {
+                top,
+                bottom,
+                shift,
+                height,
+            } => self.scroll(w, top, bottom, shift, height),
+            Command::CursorShape { shape, blink } => self.cursor_shape(w, shape, blink),
+            Command::CursorColor(c) => self.cursor_color(w, c),
         }
     }
 
@@ -143,15 +570,227 @@ impl Terminal {
 }
 
 #[derive(Debug, Clone, Copy)]
-pub enum Command {
+pub enum Command<'a> {
+    Clear,
     HideCursor,
     ShowCursor,
-    MoveCursor { x: usize, y: usize },
-    PutChar(char),
+    /// moves the cursor from `from` to `(x, y)` with `motion` computed by
+    /// `Terminal::cursor_motion`. Only `motion` is written, and the positions are kept for
+    /// debugging.
+    #[allow(dead_code)]
+    MoveCursor {
+        from: Option<(usize, usize)>,
+        x: usize,
+        y: usize,
+        motion: &'a [u8],
+    },
+    PutStr(&'a str),
+    RepeatChar { ch: char, count: usize },
+    ClearToEol,
+    EraseChars(usize),
     ResetAttr,
-    Fg(Color),
-    Bg(Color),
-    Effect(Effect),
+    ChangeAttr { from: Attribute, to: Attribute },
+    OpenLink { id: u32, url: &'a str },
+    CloseLink,
+    Scroll {
+        top: usize,
+        bottom: usize,
+        shift: isize,
+        height: usize,
+    },
+    CursorShape { shape: CursorShape, blink: bool },
+    CursorColor(Color),
+}
+
+// SGR parameters to turn on each effect, and to turn it off.
+// Note that 22 turns off both of bold and dim.
+static EFFECT_PARAMS: [(Effect, &str, &str); 5] = [
+    (Effect::BOLD, "1", "22"),
+    (Effect::DIM, "2", "22"),
+    (Effect::UNDERLINE, "4", "24"),
+    (Effect::BLINK, "5", "25"),
+    (Effect::REVERSE, "7", "27"),
+];
+
+fn push_param(params: &mut String, param: &str) {
+    if !params.is_empty() {
+        params.push(';');
+    }
+    params.push_str(param);
+}
+
+// `base` is 30 for foreground colors and 40 for background colors.
+fn push_color_param(params: &mut String, color: Color, base: u8) {
+    let param = match color {
+        Color::Default => format!("{}", base + 9),
+        Color::Black => format!("{}", base),
+        Color::Red => format!("{}", base + 1),
+        Color::Green => format!("{}", base + 2),
+        Color::Yellow => format!("{}", base + 3),
+        Color::Blue => format!("{}", base + 4),
+        Color::Magenta => format!("{}", base + 5),
+        Color::Cyan => format!("{}", base + 6),
+        Color::White => format!("{}", base + 7),
+        Color::EightBit(x) => format!("{};5;{}", base + 8, x),
+        Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+    };
+    push_param(params, &param);
+}
+
+/// returns the shortest SGR parameters that change the attribute from `from` to `to`.
+fn sgr_params(from: Attribute, to: Attribute) -> String {
+    if from == to {
+        return String::new();
+    }
+
+    let mut diff = String::new();
+    let removed = from.effect - to.effect;
+    // effects that have to be turned on, including ones turned off by "22" as a side effect.
+    let mut added = to.effect - from.effect;
+    if removed.intersects(Effect::BOLD | Effect::DIM) {
+        added |= to.effect & (Effect::BOLD | Effect::DIM);
+    }
+    let mut last_off = "";
+    for &(effect, _, off) in EFFECT_PARAMS.iter() {
+        if removed.contains(effect) && off != last_off {
+            push_param(&mut diff, off);
+            last_off = off;
+        }
+    }
+    for &(effect, on, _) in EFFECT_PARAMS.iter() {
+        if added.contains(effect) {
+            push_param(&mut diff, on);
+        }
+    }
+    if from.fg != to.fg {
+        push_color_param(&mut diff, to.fg, 30);
+    }
+    if from.bg != to.bg {
+        push_color_param(&mut diff, to.bg, 40);
+    }
+
+    let mut reset = "0".to_string();
+    for &(effect, on, _) in EFFECT_PARAMS.iter() {
+        if to.effect.contains(effect) {
+            push_param(&mut reset, on);
+        }
+    }
+    if to.fg != Color::Default {
+        push_color_param(&mut reset, to.fg, 30);
+    }
+    if to.bg != Color::Default {
+        push_color_param(&mut reset, to.bg, 40);
+    }
+
+    if reset.len() < diff.len() {
+        reset
+    } else {
+        diff
+    }
+}
+
+fn shortest<I: Iterator<Item = Vec<u8>>>(candidates: I) -> Option<Vec<u8>> {
+    candidates.min_by_key(|c| c.len())
+}
+
+// xterm's default palette for the 16 basic colors.
+static BASIC_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// approximates `color` with RGB, assuming the default palette of xterm.
+/// `Color::Default` has no RGB value because it depends on the terminal's configuration.
+fn rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let index = match color {
+        Color::Default => return None,
+        Color::Rgb(r, g, b) => return Some((r, g, b)),
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::White => 7,
+        Color::EightBit(x) => x,
+    };
+    Some(match index {
+        0..=15 => BASIC_COLORS[index as usize],
+        16..=231 => {
+            let level = |x: u8| if x == 0 { 0 } else { 55 + x * 40 };
+            let i = index - 16;
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    })
+}
+
+// control characters in a title would terminate the sequence early and let the rest of the
+// title be interpreted by the terminal.
+fn strip_controls(s: &str) -> String {
+    s.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// creates a `Terminal` with a subset of xterm's capabilities, for tests.
+#[cfg(test)]
+pub fn dumb_xterm() -> Terminal {
+    let strings: &[(&'static str, &[u8])] = &[
+        ("cup", b"\x1b[%i%p1%d;%p2%dH"),
+        ("cr", b"\r"),
+        ("cud1", b"\n"),
+        ("cud", b"\x1b[%p1%dB"),
+        ("cuu1", b"\x1b[A"),
+        ("cuu", b"\x1b[%p1%dA"),
+        ("cuf1", b"\x1b[C"),
+        ("cuf", b"\x1b[%p1%dC"),
+        ("cub1", b"\x08"),
+        ("cub", b"\x1b[%p1%dD"),
+        ("hpa", b"\x1b[%i%p1%dG"),
+        ("vpa", b"\x1b[%i%p1%dd"),
+        ("csr", b"\x1b[%i%p1%d;%p2%dr"),
+        ("ind", b"\n"),
+        ("ri", b"\x1bM"),
+        ("el", b"\x1b[K"),
+        ("ech", b"\x1b[%p1%dX"),
+        ("rep", b"%p1%c\x1b[%p2%{1}%-%db"),
+        ("sgr0", b"\x1b(B\x1b[m"),
+        ("clear", b"\x1b[H\x1b[2J"),
+    ];
+    Terminal::new(TermInfo {
+        names: vec!["xterm".to_string()],
+        bools: Default::default(),
+        numbers: Default::default(),
+        strings: strings.iter().map(|&(k, v)| (k, v.to_vec())).collect(),
+    })
+}
+
+/// returns true if the encoding of the locale is UTF-8.
+fn locale_is_utf8() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
 }
 
 pub fn size(fd: libc::c_int) -> (usize, usize) {
@@ -165,3 +804,102 @@ pub fn size(fd: libc::c_int) -> (usize, usize) {
         (wsz.ws_col as usize, wsz.ws_row as usize)
     }
 }
+
+#[test]
+fn test_rgb() {
+    assert_eq!(rgb(Color::Default), None);
+    assert_eq!(rgb(Color::Red), Some((0xcd, 0x00, 0x00)));
+    assert_eq!(rgb(Color::EightBit(16)), Some((0, 0, 0)));
+    assert_eq!(rgb(Color::EightBit(196)), Some((255, 0, 0)));
+    assert_eq!(rgb(Color::EightBit(244)), Some((128, 128, 128)));
+    assert_eq!(rgb(Color::Rgb(1, 2, 3)), Some((1, 2, 3)));
+}
+
+#[test]
+fn test_cursor_motion() {
+    let terminal = dumb_xterm();
+    let tests = [
+        (None, 10, 5, &b"\x1b[6;11H"[..]),
+        (Some((3, 5)), 3, 5, &b""[..]),
+        (Some((3, 5)), 4, 5, &b"\x1b[C"[..]),
+        (Some((3, 5)), 1, 5, &b"\x08\x08"[..]),
+        (Some((30, 5)), 0, 6, &b"\n\r"[..]),
+        (Some((3, 5)), 40, 5, &b"\x1b[37C"[..]),
+        (Some((3, 5)), 3, 4, &b"\x1b[A"[..]),
+        (Some((3, 40)), 3, 2, &b"\x1b[3d"[..]),
+    ];
+    let mut motion = Vec::new();
+    for &(from, x, y, expected) in tests.iter() {
+        terminal.cursor_motion(from, x, y, &mut motion);
+        assert_eq!(motion, expected);
+    }
+}
+
+#[test]
+fn test_sgr_params() {
+    let default = Attribute::default();
+    let red = Attribute {
+        fg: Color::Red,
+        ..default
+    };
+    let bold_dim = Attribute {
+        effect: Effect::BOLD | Effect::DIM,
+        ..red
+    };
+    let dim = Attribute {
+        effect: Effect::DIM,
+        ..red
+    };
+    let everything = Attribute {
+        fg: Color::Rgb(1, 2, 3),
+        bg: Color::EightBit(100),
+        effect: Effect::all(),
+    };
+    let tests = [
+        (default, default, ""),
+        (default, red, "31"),
+        (red, default, "0"),
+        (bold_dim, dim, "22;2"),
+        (
+            Attribute {
+                bg: Color::Blue,
+                ..red
+            },
+            red,
+            "49",
+        ),
+        (everything, default, "0"),
+        (everything, red, "0;31"),
+        (default, everything, "1;2;4;5;7;38;2;1;2;3;48;5;100"),
+    ];
+    for &(from, to, expected) in tests.iter() {
+        assert_eq!(sgr_params(from, to), expected);
+    }
+}
+
+#[test]
+fn test_repeat_char() {
+    let terminal = dumb_xterm();
+    let mut buf = Vec::new();
+    terminal.repeat_char(&mut buf, '=', 10).unwrap();
+    assert_eq!(buf, b"=\x1b[9b");
+}
+
+#[test]
+fn test_put_str_without_utf8() {
+    let mut terminal = dumb_xterm();
+    terminal.utf8 = false;
+    let mut buf = Vec::new();
+    terminal.put_str(&mut buf, "┌─┐x").unwrap();
+    assert_eq!(buf, "+-+x".as_bytes());
+
+    let strings = &mut terminal.terminfo.strings;
+    strings.insert("smacs", b"\x1b(0".to_vec());
+    strings.insert("rmacs", b"\x1b(B".to_vec());
+    strings.insert("acsc", b"``aaffggjjkkllmmnnooppqqrrssttuuvvwwxxyyzz{{||}}~~".to_vec());
+    let mut terminal = Terminal::new(terminal.terminfo);
+    terminal.utf8 = false;
+    buf.clear();
+    terminal.put_str(&mut buf, "┏━┓x║-あ").unwrap();
+    assert_eq!(buf, "\x1b(0lqk\x1b(Bx\x1b(0x\x1b(B-あ".as_bytes());
+}
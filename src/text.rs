@@ -0,0 +1,523 @@
+//! This module provides styled text, made of spans with their own attributes, and a small
+//! markup language to write it.
+//!
+//! ```
+//! use termfest::attr::{Attribute, Color, Effect};
+//! use termfest::text::{Span, Text};
+//!
+//! let text = Text::from_markup("[bold red]error[/]: file not found");
+//! let error = Attribute { fg: Color::Red, effect: Effect::BOLD, ..Attribute::default() };
+//! assert_eq!(text.lines[0].spans[0], Span::styled("error", error));
+//! assert_eq!(text.lines[0].spans[1], Span::raw(": file not found"));
+//! ```
+//!
+//! A tag like `[bold red]` applies styles to the text until the matching `[/]`, and tags can be
+//! nested. A tag consists of space-separated words:
+//!
+//! - effects: `bold`, `dim`, `underline`, `blink` and `reverse`
+//! - foreground colors: `default`, `black`, `red`, `green`, `yellow`, `blue`, `magenta`,
+//!   `cyan`, `white`, a 256-color index like `208`, or a true color like `#ff8700`
+//! - background colors: `on` followed by a color, like `on blue`
+//!
+//! `\[` and `\\` are a literal `[` and `\`, and anything in brackets which is not a valid tag is
+//! kept as it is. Use `escape_markup` to embed arbitrary strings.
+//!
+//! `Text::from_ansi` reads the output of other commands colored with SGR escape sequences, like
+//! `git diff --color`.
+
+use attr::{Attribute, Color, Effect};
+use width::DisplayWidth;
+
+/// `Span` is a string rendered with a single attribute.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub content: String,
+    pub attr: Attribute,
+}
+
+impl Span {
+    /// creates a span with the default attribute.
+    pub fn raw<S: Into<String>>(content: S) -> Span {
+        Span::styled(content, Attribute::default())
+    }
+
+    pub fn styled<S: Into<String>>(content: S, attr: Attribute) -> Span {
+        Span {
+            content: content.into(),
+            attr,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.content.display_width()
+    }
+}
+
+impl<'a> From<&'a str> for Span {
+    fn from(s: &'a str) -> Span {
+        Span::raw(s)
+    }
+}
+
+impl From<String> for Span {
+    fn from(s: String) -> Span {
+        Span::raw(s)
+    }
+}
+
+/// `Line` is a sequence of spans rendered in a row.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Line {
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    pub fn new(spans: Vec<Span>) -> Line {
+        Line { spans }
+    }
+
+    pub fn push<S: Into<Span>>(&mut self, span: S) {
+        self.spans.push(span.into());
+    }
+
+    pub fn width(&self) -> usize {
+        self.spans.iter().map(Span::width).sum()
+    }
+}
+
+impl<'a> From<&'a str> for Line {
+    fn from(s: &'a str) -> Line {
+        Line::new(vec![Span::raw(s)])
+    }
+}
+
+impl From<Span> for Line {
+    fn from(span: Span) -> Line {
+        Line::new(vec![span])
+    }
+}
+
+impl From<Vec<Span>> for Line {
+    fn from(spans: Vec<Span>) -> Line {
+        Line::new(spans)
+    }
+}
+
+/// `Text` is a sequence of lines.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Text {
+    pub lines: Vec<Line>,
+}
+
+impl Text {
+    pub fn new(lines: Vec<Line>) -> Text {
+        Text { lines }
+    }
+
+    /// parses the markup `s`. See the module documentation for the syntax.
+    pub fn from_markup(s: &str) -> Text {
+        let mut parser = Parser {
+            builder: Builder::new(),
+            styles: Vec::new(),
+        };
+        let mut rest = s;
+        while let Some(ch) = rest.chars().next() {
+            rest = &rest[ch.len_utf8()..];
+            match ch {
+                '\\' if rest.starts_with('[') || rest.starts_with('\\') => {
+                    parser.builder.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+                '[' => {
+                    let end = rest.find(']');
+                    match end.and_then(|end| parser.tag(&rest[..end]).map(|_| end)) {
+                        Some(end) => rest = &rest[end + 1..],
+                        None => parser.builder.push_str("["),
+                    }
+                }
+                '\n' => parser.builder.new_line(),
+                _ => parser.builder.push(ch),
+            }
+        }
+        parser.builder.text
+    }
+
+    /// parses `s` containing SGR escape sequences (`ESC [ ... m`), and converts them into the
+    /// attributes of spans. 16 colors, 256 colors and true colors are supported, and other escape
+    /// sequences are removed, as well as control characters except tabs and newlines.
+    ///
+    /// ```
+    /// use termfest::attr::{Attribute, Color};
+    /// use termfest::text::{Span, Text};
+    ///
+    /// let text = Text::from_ansi("\x1b[31m-removed\x1b[0m");
+    /// let red = Attribute { fg: Color::Red, ..Attribute::default() };
+    /// assert_eq!(text.lines[0].spans, [Span::styled("-removed", red)]);
+    /// ```
+    pub fn from_ansi(s: &str) -> Text {
+        let mut builder = Builder::new();
+        let mut rest = s;
+        while let Some(ch) = rest.chars().next() {
+            rest = &rest[ch.len_utf8()..];
+            match ch {
+                '\x1b' | '\u{9b}' => {
+                    let (len, sgr) = escape_sequence(ch, rest);
+                    if let Some(params) = sgr {
+                        apply_sgr(params, &mut builder.attr);
+                    }
+                    rest = &rest[len..];
+                }
+                '\n' => builder.new_line(),
+                '\t' => builder.push(ch),
+                // control characters like BEL would be interpreted by the terminal.
+                _ if ch.is_control() => {}
+                _ => builder.push(ch),
+            }
+        }
+        builder.text
+    }
+
+    /// returns the width of the widest line.
+    pub fn width(&self) -> usize {
+        self.lines.iter().map(Line::width).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+impl<'a> From<&'a str> for Text {
+    fn from(s: &'a str) -> Text {
+        Text::new(s.lines().map(Line::from).collect())
+    }
+}
+
+impl From<Line> for Text {
+    fn from(line: Line) -> Text {
+        Text::new(vec![line])
+    }
+}
+
+/// escapes `s` so that `Text::from_markup` reads it as a plain string.
+///
+/// ```
+/// use termfest::text::{escape_markup, Text};
+///
+/// let name = "[not a tag]";
+/// let text = Text::from_markup(&format!("[bold]{}[/]", escape_markup(name)));
+/// assert_eq!(text.lines[0].spans[0].content, "[not a tag]");
+/// ```
+pub fn escape_markup(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('[', "\\[")
+}
+
+// `Builder` builds a `Text` from strings and attributes, joining strings with the same
+// attribute into a span.
+struct Builder {
+    text: Text,
+    attr: Attribute,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            text: Text::new(vec![Line::default()]),
+            attr: Attribute::default(),
+        }
+    }
+
+    fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    fn push_str(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        let spans = &mut self.text.lines.last_mut().unwrap().spans;
+        match spans.last_mut() {
+            Some(span) if span.attr == self.attr => span.content.push_str(s),
+            _ => spans.push(Span::styled(s, self.attr)),
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.text.lines.push(Line::default());
+    }
+}
+
+struct Parser {
+    builder: Builder,
+    // attributes of the open tags.
+    styles: Vec<Attribute>,
+}
+
+impl Parser {
+    /// applies the tag `tag` (without brackets), or returns `None` if it is not a valid tag.
+    fn tag(&mut self, tag: &str) -> Option<()> {
+        if tag.contains('\n') {
+            return None;
+        }
+        if tag.starts_with('/') {
+            self.styles.pop();
+        } else {
+            let attr = parse_style(tag, self.builder.attr)?;
+            self.styles.push(attr);
+        }
+        self.builder.attr = self.styles.last().cloned().unwrap_or_default();
+        Some(())
+    }
+}
+
+/// applies the style words in `style` to `attr`.
+fn parse_style(style: &str, mut attr: Attribute) -> Option<Attribute> {
+    let mut words = style.split_whitespace().peekable();
+    words.peek()?;
+    while let Some(word) = words.next() {
+        let effect = match word {
+            "bold" => Effect::BOLD,
+            "dim" => Effect::DIM,
+            "underline" => Effect::UNDERLINE,
+            "blink" => Effect::BLINK,
+            "reverse" => Effect::REVERSE,
+            "on" => {
+                attr.bg = parse_color(words.next()?)?;
+                continue;
+            }
+            _ => {
+                attr.fg = parse_color(word)?;
+                continue;
+            }
+        };
+        attr.effect |= effect;
+    }
+    Some(attr)
+}
+
+/// returns the length of the escape sequence following `ch` (ESC or CSI) in `rest`, and the
+/// parameters if it is a SGR sequence. An unterminated sequence spans the rest of the string.
+fn escape_sequence(ch: char, rest: &str) -> (usize, Option<&str>) {
+    let bytes = rest.as_bytes();
+    let start = if ch == '\x1b' {
+        match bytes.first() {
+            Some(&b'[') => 1,
+            // OSC, DCS, SOS, PM and APC are terminated by ST (or BEL for OSC).
+            Some(&b']') | Some(&b'P') | Some(&b'X') | Some(&b'^') | Some(&b'_') => {
+                let end = bytes[1..]
+                    .iter()
+                    .enumerate()
+                    .find(|&(i, &b)| b == 0x07 || (b == 0x1b && bytes.get(i + 2) == Some(&b'\\')))
+                    .map(|(i, &b)| if b == 0x07 { i + 2 } else { i + 3 });
+                return (end.unwrap_or(bytes.len()), None);
+            }
+            // other sequences are intermediate bytes followed by a final byte.
+            _ => {
+                let end = bytes.iter().position(|&b| !(0x20..=0x2f).contains(&b));
+                return (end.map_or(bytes.len(), |i| i + 1), None);
+            }
+        }
+    } else {
+        0
+    };
+    // a control sequence is parameter bytes, intermediate bytes and a final byte.
+    match bytes[start..].iter().position(|&b| !(0x20..=0x3f).contains(&b)) {
+        Some(i) if (0x40..=0x7e).contains(&bytes[start + i]) => {
+            let sgr = if bytes[start + i] == b'm' {
+                Some(&rest[start..start + i])
+            } else {
+                None
+            };
+            (start + i + 1, sgr)
+        }
+        // a broken sequence is removed up to the unexpected byte.
+        Some(i) => (start + i, None),
+        None => (bytes.len(), None),
+    }
+}
+
+/// applies the parameters of a SGR sequence to `attr`.
+fn apply_sgr(params: &str, attr: &mut Attribute) {
+    let mut params = params.split(';');
+    while let Some(param) = params.next() {
+        if param.contains(':') {
+            // sub-parameters like `38:2::255:135:0` or `4:3`.
+            let sub: Vec<&str> = param.split(':').collect();
+            match sub[0] {
+                "38" => attr.fg = colon_color(&sub[1..]).unwrap_or(attr.fg),
+                "48" => attr.bg = colon_color(&sub[1..]).unwrap_or(attr.bg),
+                "4" if sub[1] == "0" => attr.effect.remove(Effect::UNDERLINE),
+                "4" => attr.effect.insert(Effect::UNDERLINE),
+                _ => {}
+            }
+            continue;
+        }
+        let code = if param.is_empty() {
+            0
+        } else {
+            match param.parse::<u16>() {
+                Ok(code) => code,
+                Err(_) => continue,
+            }
+        };
+        match code {
+            0 => *attr = Attribute::default(),
+            1 => attr.effect.insert(Effect::BOLD),
+            2 => attr.effect.insert(Effect::DIM),
+            4 => attr.effect.insert(Effect::UNDERLINE),
+            5 | 6 => attr.effect.insert(Effect::BLINK),
+            7 => attr.effect.insert(Effect::REVERSE),
+            22 => attr.effect.remove(Effect::BOLD | Effect::DIM),
+            24 => attr.effect.remove(Effect::UNDERLINE),
+            25 => attr.effect.remove(Effect::BLINK),
+            27 => attr.effect.remove(Effect::REVERSE),
+            30..=37 => attr.fg = basic_color(code - 30),
+            38 => attr.fg = semicolon_color(&mut params).unwrap_or(attr.fg),
+            39 => attr.fg = Color::Default,
+            40..=47 => attr.bg = basic_color(code - 40),
+            48 => attr.bg = semicolon_color(&mut params).unwrap_or(attr.bg),
+            49 => attr.bg = Color::Default,
+            90..=97 => attr.fg = Color::EightBit((code - 90 + 8) as u8),
+            100..=107 => attr.bg = Color::EightBit((code - 100 + 8) as u8),
+            _ => {}
+        }
+    }
+}
+
+fn basic_color(index: u16) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+/// reads the color of `38;5;n` or `38;2;r;g;b` after `38` (or `48`).
+fn semicolon_color<'a, I: Iterator<Item = &'a str>>(params: &mut I) -> Option<Color> {
+    let mut next = || params.next().and_then(|p| p.parse::<u8>().ok());
+    match next()? {
+        5 => Some(Color::EightBit(next()?)),
+        2 => Some(Color::Rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// reads the color of `38:5:n`, `38:2:r:g:b` or `38:2:id:r:g:b` from the sub-parameters after
+/// `38` (or `48`).
+fn colon_color(sub: &[&str]) -> Option<Color> {
+    let n = |i: usize| sub.get(i).and_then(|p| p.parse::<u8>().ok());
+    match *sub.first()? {
+        "5" => Some(Color::EightBit(n(1)?)),
+        // the color space id is optional.
+        "2" if sub.len() >= 5 => Some(Color::Rgb(n(2)?, n(3)?, n(4)?)),
+        "2" => Some(Color::Rgb(n(1)?, n(2)?, n(3)?)),
+        _ => None,
+    }
+}
+
+fn parse_color(word: &str) -> Option<Color> {
+    let color = match word {
+        "default" => Color::Default,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        _ if word.starts_with('#') && word.len() == 7 => {
+            let component = |i| u8::from_str_radix(word.get(i..i + 2)?, 16).ok();
+            Color::Rgb(component(1)?, component(3)?, component(5)?)
+        }
+        _ => Color::EightBit(word.parse().ok()?),
+    };
+    Some(color)
+}
+
+#[test]
+fn test_from_markup() {
+    let red = Attribute {
+        fg: Color::Red,
+        ..Attribute::default()
+    };
+    let bold_red_on_blue = Attribute {
+        bg: Color::Blue,
+        effect: Effect::BOLD,
+        ..red
+    };
+    assert_eq!(
+        Text::from_markup("a[red]b[bold on blue]c[/]d[/]e\n[208]f"),
+        Text::new(vec![
+            Line::new(vec![
+                Span::raw("a"),
+                Span::styled("b", red),
+                Span::styled("c", bold_red_on_blue),
+                Span::styled("d", red),
+                Span::raw("e"),
+            ]),
+            Line::new(vec![
+                Span::styled(
+                    "f",
+                    Attribute {
+                        fg: Color::EightBit(208),
+                        ..Attribute::default()
+                    },
+                ),
+            ]),
+        ])
+    );
+
+    // escapes, invalid tags and unbalanced brackets are kept as they are.
+    assert_eq!(
+        Text::from_markup("\\[red] [nope] [#12345g] [ [/] a\\\\b \\c"),
+        Text::from("[red] [nope] [#12345g] [  a\\b \\c")
+    );
+    assert_eq!(
+        Text::from_markup("[#ff8700 on 0]x"),
+        Text::from(Line::from(Span::styled(
+            "x",
+            Attribute {
+                fg: Color::Rgb(0xff, 0x87, 0x00),
+                bg: Color::EightBit(0),
+                ..Attribute::default()
+            }
+        )))
+    );
+}
+
+#[test]
+fn test_from_ansi() {
+    let attr = |fg, bg, effect| Attribute { fg, bg, effect };
+    let text = Text::from_ansi(concat!(
+        "\x1b[1;31ma\x1b[22;4;42mb\x1b[38;5;208;48;2;1;2;3mc",
+        "\x1b[38:2::4:5:6m\x1b[Kd\x1b]8;;http://example.com\x1b\\e\x07\x1b[0m\r\n",
+        "\x1b(Bf\x1b[9;39mg\u{9b}7mh\x08\ti\r\x7f\u{85}\x1b[1",
+    ));
+    assert_eq!(
+        text,
+        Text::new(vec![
+            Line::new(vec![
+                Span::styled("a", attr(Color::Red, Color::Default, Effect::BOLD)),
+                Span::styled("b", attr(Color::Red, Color::Green, Effect::UNDERLINE)),
+                Span::styled(
+                    "c",
+                    attr(Color::EightBit(208), Color::Rgb(1, 2, 3), Effect::UNDERLINE),
+                ),
+                Span::styled(
+                    "de",
+                    attr(Color::Rgb(4, 5, 6), Color::Rgb(1, 2, 3), Effect::UNDERLINE),
+                ),
+            ]),
+            Line::new(vec![
+                Span::raw("fg"),
+                Span::styled("h\ti", attr(Color::Default, Color::Default, Effect::REVERSE)),
+            ]),
+        ])
+    );
+}
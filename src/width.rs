@@ -0,0 +1,157 @@
+//! This module defines how termfest measures the display width of characters and strings.
+//!
+//! Characters of East Asian ambiguous width, like box-drawing characters, '○' and '×', are
+//! one column wide in most terminals, but two columns wide in terminals configured for CJK.
+//! The width policy is shared by the whole process, and is best set before holding the terminal.
+//! If it is changed later, the next flush blanks characters in the buffer that no longer fit
+//! their cells, like a character that has become wide with no room for its right half, and
+//! repaints the terminal if there are such characters.
+//!
+//! ```
+//! use termfest::width::{self, AmbiguousWidth, WidthPolicy};
+//!
+//! width::set_width_policy(WidthPolicy {
+//!     ambiguous: AmbiguousWidth::Wide,
+//!     overrides: vec![('\u{2500}', '\u{257f}', 1)],
+//! });
+//! ```
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// `AmbiguousWidth` is the width of characters of East Asian ambiguous width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmbiguousWidth {
+    #[default]
+    Narrow,
+    Wide,
+}
+
+/// `WidthPolicy` decides the display width of each character.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WidthPolicy {
+    pub ambiguous: AmbiguousWidth,
+    /// inclusive ranges of characters and their widths, taking precedence over the unicode
+    /// tables. If ranges overlap, the first one wins.
+    pub overrides: Vec<(char, char, usize)>,
+}
+
+impl WidthPolicy {
+    /// returns the display width of `ch` under this policy.
+    pub fn char_width(&self, ch: char) -> usize {
+        if let Some(&(_, _, width)) = self.overrides
+            .iter()
+            .find(|&&(first, last, _)| first <= ch && ch <= last)
+        {
+            return width;
+        }
+        let width = match self.ambiguous {
+            AmbiguousWidth::Narrow => ch.width(),
+            AmbiguousWidth::Wide => ch.width_cjk(),
+        };
+        width.unwrap_or(1)
+    }
+}
+
+// true if the policy differs from the default, so that the common case needs no lock.
+static CUSTOMIZED: AtomicBool = AtomicBool::new(false);
+static POLICY: RwLock<WidthPolicy> = RwLock::new(WidthPolicy {
+    ambiguous: AmbiguousWidth::Narrow,
+    overrides: Vec::new(),
+});
+// incremented whenever the policy is set, so that screens can notice the change.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// sets the width policy of the process.
+pub fn set_width_policy(policy: WidthPolicy) {
+    let customized = policy != WidthPolicy::default();
+    *POLICY.write().unwrap() = policy;
+    CUSTOMIZED.store(customized, Ordering::SeqCst);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// returns a number which changes whenever the width policy is set.
+pub(crate) fn policy_generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// returns the current width policy of the process.
+pub fn width_policy() -> WidthPolicy {
+    POLICY.read().unwrap().clone()
+}
+
+/// `DisplayWidth` provides a way to determine display width of characters or strings.
+/// The width of a string is the sum of the widths of its extended grapheme clusters, as
+/// termfest renders each cluster in a cell.
+/// Widths follow the width policy of the process.
+///
+/// ```
+/// use termfest::DisplayWidth;
+///
+/// assert_eq!('あ'.display_width(), 2);
+/// assert_eq!('a'.display_width(), 1);
+///
+/// assert_eq!("abc".display_width(), 3);
+/// assert_eq!("あいう".display_width(), 6);
+/// assert_eq!("e\u{301}".display_width(), 1);
+/// assert_eq!("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}".display_width(), 2);
+/// ```
+pub trait DisplayWidth {
+    fn display_width(&self) -> usize;
+}
+
+impl DisplayWidth for char {
+    fn display_width(&self) -> usize {
+        if CUSTOMIZED.load(Ordering::Relaxed) {
+            POLICY.read().unwrap().char_width(*self)
+        } else {
+            self.width().unwrap_or(1)
+        }
+    }
+}
+
+impl DisplayWidth for str {
+    fn display_width(&self) -> usize {
+        self.graphemes(true).map(grapheme_width).sum()
+    }
+}
+
+/// returns the display width of an extended grapheme cluster.
+/// Terminals render a cluster with the width of its base character, except that emoji
+/// presentation selectors, flags and ZWJ sequences of emoji are two columns wide.
+pub(crate) fn grapheme_width(g: &str) -> usize {
+    let mut chars = g.chars();
+    let base = match chars.next() {
+        Some(ch) => ch,
+        None => return 0,
+    };
+    let width = base.display_width();
+    let is_regional_indicator = |ch| ('\u{1f1e6}'..='\u{1f1ff}').contains(&ch);
+    for ch in chars {
+        if ch == '\u{fe0f}' || (is_regional_indicator(ch) && is_regional_indicator(base)) {
+            return 2;
+        }
+    }
+    width
+}
+
+#[test]
+fn test_char_width() {
+    let narrow = WidthPolicy::default();
+    assert_eq!(narrow.char_width('─'), 1);
+    assert_eq!(narrow.char_width('○'), 1);
+    assert_eq!(narrow.char_width('あ'), 2);
+
+    let wide = WidthPolicy {
+        ambiguous: AmbiguousWidth::Wide,
+        overrides: vec![('\u{2500}', '\u{257f}', 1), ('a', 'a', 2)],
+    };
+    assert_eq!(wide.char_width('─'), 1);
+    assert_eq!(wide.char_width('○'), 2);
+    assert_eq!(wide.char_width('あ'), 2);
+    assert_eq!(wide.char_width('a'), 2);
+    assert_eq!(wide.char_width('b'), 1);
+}
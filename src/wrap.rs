@@ -0,0 +1,195 @@
+//! This module provides word-wrapping and truncation of strings to a display width.
+//!
+//! ```
+//! use termfest::wrap::{self, Ellipsis};
+//!
+//! assert_eq!(wrap::wrap("the quick brown fox", 10, 8), ["the quick", "brown fox"]);
+//! assert_eq!(wrap::truncate("the quick brown fox", 10, Ellipsis::End), "the quick…");
+//! ```
+
+use std::borrow::Cow;
+
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_segmentation::UnicodeSegmentation;
+
+use width::DisplayWidth;
+
+/// `Ellipsis` is where a truncated string is cut and marked with '…'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ellipsis {
+    /// keeps the end of the string, like "…/src/lib.rs".
+    Start,
+    /// keeps both ends of the string, like "/home/…/lib.rs".
+    Middle,
+    /// keeps the start of the string, like "/home/user/…".
+    End,
+}
+
+/// `Align` is how a string shorter than the available width is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    /// returns the offset of a string of `width` columns placed in `available` columns.
+    pub fn offset(&self, width: usize, available: usize) -> usize {
+        let space = available.saturating_sub(width);
+        match *self {
+            Align::Left => 0,
+            Align::Center => space / 2,
+            Align::Right => space,
+        }
+    }
+}
+
+/// splits `s` into lines of at most `width` columns, breaking at the line break opportunities
+/// of Unicode (UAX #14). Newlines in `s` always break lines, and whitespace at the end of each
+/// line is removed. A word longer than `width` is broken between grapheme clusters.
+/// Tabs are measured as `print` expands them, up to the next multiple of `tab_width` columns
+/// from the start of the line, or as one column if `tab_width` is 0.
+pub fn wrap(s: &str, width: usize, tab_width: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    // the current line is `s[start..end]`, where `end` is a break opportunity.
+    let mut start = 0;
+    let mut end = 0;
+    let columns = |s: &str| prefix_columns(s, usize::MAX, tab_width).1;
+    for (position, opportunity) in linebreaks(s) {
+        let mut broken = false;
+        if end > start && columns(s[start..position].trim_end()) > width {
+            lines.push(s[start..end].trim_end());
+            start = end;
+        }
+        while columns(s[start..position].trim_end()) > width {
+            let rest = &s[start..position];
+            // put at least one grapheme cluster in a line.
+            let len = match prefix_columns(rest, width, tab_width).0 {
+                0 => rest.graphemes(true).next().map_or(0, str::len),
+                len => len,
+            };
+            lines.push(&s[start..start + len]);
+            start += len;
+            broken = true;
+        }
+        end = position;
+        // a word broken at its end leaves no line to finish.
+        if opportunity == BreakOpportunity::Mandatory && !(broken && start == end) {
+            lines.push(s[start..end].trim_end());
+            start = end;
+        }
+    }
+    lines
+}
+
+/// shortens `s` to at most `width` columns, replacing the cut part with '…'.
+/// If `s` fits in `width`, it is returned as it is. Tabs are measured as one column.
+pub fn truncate(s: &str, width: usize, ellipsis: Ellipsis) -> String {
+    if s.display_width() <= width {
+        return s.to_string();
+    }
+    let mark = "…";
+    let available = match width.checked_sub(mark.display_width()) {
+        Some(available) => available,
+        // there is no room even for the ellipsis.
+        None => return s[..prefix_len(s, width)].to_string(),
+    };
+    match ellipsis {
+        Ellipsis::Start => format!("{}{}", mark, &s[s.len() - suffix_len(s, available)..]),
+        Ellipsis::Middle => {
+            let head = &s[..prefix_len(s, available.div_ceil(2))];
+            let rest = available - head.display_width();
+            format!("{}{}{}", head, mark, &s[s.len() - suffix_len(s, rest)..])
+        }
+        Ellipsis::End => format!("{}{}", &s[..prefix_len(s, available)], mark),
+    }
+}
+
+/// replaces tabs in `s` with the spaces that `print` puts for them from the start of the line.
+pub(crate) fn expand_tabs(s: &str, tab_width: usize) -> Cow<'_, str> {
+    if tab_width == 0 || !s.contains('\t') {
+        return Cow::Borrowed(s);
+    }
+    let mut expanded = String::with_capacity(s.len());
+    let mut column = 0;
+    for g in s.graphemes(true) {
+        if g == "\t" {
+            let stop = (column / tab_width + 1) * tab_width;
+            expanded.extend((column..stop).map(|_| ' '));
+            column = stop;
+            continue;
+        }
+        expanded.push_str(g);
+        column += g.display_width();
+    }
+    Cow::Owned(expanded)
+}
+
+/// returns the byte length of the longest prefix of `s` fitting in `width` columns.
+fn prefix_len(s: &str, width: usize) -> usize {
+    prefix_columns(s, width, 0).0
+}
+
+/// returns the byte length and the width of the longest prefix of `s` fitting in `width`
+/// columns, expanding tabs to multiples of `tab_width` columns.
+fn prefix_columns(s: &str, width: usize, tab_width: usize) -> (usize, usize) {
+    let mut len = 0;
+    let mut w = 0;
+    for g in s.graphemes(true) {
+        let next = if g == "\t" && tab_width > 0 {
+            (w / tab_width + 1) * tab_width
+        } else {
+            w + g.display_width()
+        };
+        if next > width {
+            break;
+        }
+        len += g.len();
+        w = next;
+    }
+    (len, w)
+}
+
+/// returns the byte length of the longest suffix of `s` fitting in `width` columns.
+fn suffix_len(s: &str, width: usize) -> usize {
+    let mut len = 0;
+    let mut w = 0;
+    for g in s.graphemes(true).rev() {
+        w += g.display_width();
+        if w > width {
+            break;
+        }
+        len += g.len();
+    }
+    len
+}
+
+#[test]
+fn test_wrap() {
+    assert_eq!(wrap("the quick brown fox", 10, 8), ["the quick", "brown fox"]);
+    assert_eq!(wrap("a  b\nc d", 3, 8), ["a", "b", "c d"]);
+    assert_eq!(wrap("abcdefgh ij", 3, 8), ["abc", "def", "gh", "ij"]);
+    assert_eq!(wrap("日本語の文章", 5, 8), ["日本", "語の", "文章"]);
+    assert_eq!(wrap("日本", 1, 8), ["日", "本"]);
+    assert_eq!(wrap("a\n\nb", 1, 8), ["a", "", "b"]);
+    assert!(wrap("", 5, 8).is_empty());
+    // a tab reaches the next tab stop.
+    assert_eq!(wrap("ab\tcd ef", 8, 4), ["ab\tcd", "ef"]);
+    assert_eq!(wrap("ab\tcd ef", 8, 0), ["ab\tcd ef"]);
+    assert_eq!(wrap("abcdefg\th", 8, 4), ["abcdefg", "h"]);
+}
+
+#[test]
+fn test_truncate() {
+    let path = "/home/user/src/lib.rs";
+    assert_eq!(truncate(path, 30, Ellipsis::End), path);
+    assert_eq!(truncate(path, 10, Ellipsis::Start), "…rc/lib.rs");
+    assert_eq!(truncate(path, 10, Ellipsis::Middle), "/home…b.rs");
+    assert_eq!(truncate(path, 10, Ellipsis::End), "/home/use…");
+    assert_eq!(truncate("日本語", 4, Ellipsis::End), "日…");
+    assert_eq!(truncate("abc", 0, Ellipsis::End), "");
+    assert_eq!(expand_tabs("a\tb\tc", 4), "a   b   c");
+    assert_eq!(Align::Center.offset(3, 10), 3);
+    assert_eq!(Align::Right.offset(3, 10), 7);
+}